@@ -0,0 +1,70 @@
+use std::{
+    any::TypeId,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+const SLOTS: usize = 64;
+
+/// Counting bloom filter over the component types of one entity.
+///
+/// Answers "definitely not present" without touching the component store.
+/// Counters instead of bits allow removing types again.
+#[derive(Clone)]
+pub(crate) struct ComponentBloom {
+    counters: [u16; SLOTS],
+}
+
+impl Default for ComponentBloom {
+    fn default() -> Self {
+        Self {
+            counters: [0; SLOTS],
+        }
+    }
+}
+
+impl ComponentBloom {
+    fn slots(type_id: TypeId) -> [usize; 2] {
+        let mut hasher = DefaultHasher::new();
+        type_id.hash(&mut hasher);
+        let hash = hasher.finish();
+        [hash as usize % SLOTS, (hash >> 32) as usize % SLOTS]
+    }
+
+    pub fn insert(&mut self, type_id: TypeId) {
+        for slot in Self::slots(type_id) {
+            self.counters[slot] = self.counters[slot].saturating_add(1);
+        }
+    }
+
+    pub fn remove(&mut self, type_id: TypeId) {
+        for slot in Self::slots(type_id) {
+            self.counters[slot] = self.counters[slot].saturating_sub(1);
+        }
+    }
+
+    pub fn may_contain(&self, type_id: TypeId) -> bool {
+        Self::slots(type_id)
+            .into_iter()
+            .all(|slot| self.counters[slot] > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_remove() {
+        let mut bloom = ComponentBloom::default();
+        assert!(!bloom.may_contain(TypeId::of::<i32>()));
+
+        bloom.insert(TypeId::of::<i32>());
+        bloom.insert(TypeId::of::<f32>());
+        assert!(bloom.may_contain(TypeId::of::<i32>()));
+        assert!(bloom.may_contain(TypeId::of::<f32>()));
+
+        bloom.remove(TypeId::of::<i32>());
+        assert!(bloom.may_contain(TypeId::of::<f32>()));
+    }
+}
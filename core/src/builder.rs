@@ -0,0 +1,107 @@
+use crate::{entity::Entity, entity_component_manager::EntityComponentManager, error::EcsError};
+
+type PendingInsert = Box<dyn FnOnce(&mut EntityComponentManager, Entity)>;
+
+/// Collects the components and parent of a new entity, created by
+/// [`EntityComponentManager::spawn`].
+///
+/// Nothing touches the manager until [`Self::build`] or [`Self::id`] is called.
+pub struct EntityBuilder<'a> {
+    manager: &'a mut EntityComponentManager,
+    components: Vec<PendingInsert>,
+    parent: Option<Entity>,
+}
+
+impl<'a> EntityBuilder<'a> {
+    pub(crate) fn new(manager: &'a mut EntityComponentManager) -> Self {
+        Self {
+            manager,
+            components: Vec::new(),
+            parent: None,
+        }
+    }
+
+    pub fn with<T: 'static>(mut self, component: T) -> Self {
+        self.components.push(Box::new(move |manager, entity| {
+            manager.insert_component(entity, component)
+        }));
+        self
+    }
+
+    pub fn child_of(mut self, parent: Entity) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+
+    /// Creates the entity, fails without creating anything if the parent is not alive.
+    pub fn build(self) -> Result<Entity, EcsError> {
+        let entity = match self.parent {
+            Some(parent) => self.manager.create_child_entity(parent)?,
+            None => self.manager.create_entity(),
+        };
+        for insert in self.components {
+            insert(self.manager, entity);
+        }
+        Ok(entity)
+    }
+
+    /// Same as [`Self::build`].
+    pub fn id(self) -> Result<Entity, EcsError> {
+        self.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Position(i32, i32);
+    #[derive(Debug, PartialEq)]
+    struct Velocity(i32, i32);
+
+    #[test]
+    fn build_with_components_and_parent() {
+        let mut manager = EntityComponentManager::default();
+        let parent = manager.create_entity();
+
+        let entity = manager
+            .spawn()
+            .with(Position(1, 2))
+            .with(Velocity(3, 4))
+            .child_of(parent)
+            .id()
+            .unwrap();
+
+        assert!(manager.is_alive(entity));
+        assert_eq!(
+            manager.get_component::<Position>(entity),
+            Some(&Position(1, 2))
+        );
+        assert_eq!(
+            manager.get_component::<Velocity>(entity),
+            Some(&Velocity(3, 4))
+        );
+        assert_eq!(*manager.get_parent(entity).unwrap(), parent);
+        assert_eq!(manager.get_children(parent).unwrap(), &vec![entity]);
+    }
+
+    #[test]
+    fn build_with_dead_parent() {
+        let mut manager = EntityComponentManager::default();
+        let root = manager.create_entity();
+        let parent = manager.create_child_entity(root).unwrap();
+        manager.delete_entity(parent);
+        let next = manager.next_entity_id();
+
+        let result = manager
+            .spawn()
+            .with(Position(1, 2))
+            .child_of(parent)
+            .build();
+
+        assert!(matches!(result, Err(EcsError::EntityNotFound(entity, _)) if entity == parent));
+        assert_eq!(manager.next_entity_id(), next);
+        assert!(manager.queue_component::<Position>().is_empty());
+    }
+}
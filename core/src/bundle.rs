@@ -0,0 +1,69 @@
+use crate::{entity::Entity, entity_component_manager::EntityComponentManager};
+
+/// A group of components inserted or removed together.
+///
+/// Implemented for tuples up to arity 4, see [`EntityComponentManager::insert_bundle`]
+/// and [`EntityComponentManager::remove_bundle`].
+pub trait Bundle {
+    /// The removed components, one `Option` per bundle member.
+    type Removed;
+
+    fn insert(self, manager: &mut EntityComponentManager, entity: Entity);
+
+    fn remove(manager: &mut EntityComponentManager, entity: Entity) -> Self::Removed;
+}
+
+macro_rules! impl_bundle {
+    ($($component:ident),+) => {
+        impl<$($component: 'static),+> Bundle for ($($component,)+) {
+            type Removed = ($(Option<$component>,)+);
+
+            #[allow(non_snake_case)]
+            fn insert(self, manager: &mut EntityComponentManager, entity: Entity) {
+                let ($($component,)+) = self;
+                $(manager.insert_component(entity, $component);)+
+            }
+
+            fn remove(manager: &mut EntityComponentManager, entity: Entity) -> Self::Removed {
+                ($(manager.take_component::<$component>(entity),)+)
+            }
+        }
+    };
+}
+
+impl_bundle!(A);
+impl_bundle!(A, B);
+impl_bundle!(A, B, C);
+impl_bundle!(A, B, C, D);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Position(i32, i32);
+    #[derive(Debug, PartialEq)]
+    struct Velocity(i32, i32);
+    #[derive(Debug, PartialEq)]
+    struct Health(u32);
+
+    #[test]
+    fn insert_and_remove_bundle() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_bundle(entity, (Position(1, 2), Velocity(3, 4), Health(10)));
+        assert_eq!(manager.get_component::<Health>(entity), Some(&Health(10)));
+
+        let removed = manager.remove_bundle::<(Position, Velocity, Health)>(entity);
+        assert_eq!(
+            removed,
+            (Some(Position(1, 2)), Some(Velocity(3, 4)), Some(Health(10)))
+        );
+        assert!(!manager.has_component::<Position>(entity));
+        assert!(!manager.has_component::<Velocity>(entity));
+        assert!(!manager.has_component::<Health>(entity));
+
+        let removed = manager.remove_bundle::<(Position, Health)>(entity);
+        assert_eq!(removed, (None, None));
+    }
+}
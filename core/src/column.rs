@@ -0,0 +1,259 @@
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+};
+
+use crate::{dense::DenseStore, entity::Entity, storage::Storage};
+
+/// Type-erased operations on the column holding every component of one type.
+pub(crate) trait ComponentColumn {
+    fn type_name(&self) -> &'static str;
+
+    /// Components of another type are ignored.
+    fn insert_boxed(
+        &mut self,
+        entity: Entity,
+        component: Box<dyn Any>,
+        sequence: u64,
+    ) -> Option<Box<dyn Any>>;
+
+    fn remove_boxed(&mut self, entity: Entity) -> Option<Box<dyn Any>>;
+
+    fn get_any(&self, entity: Entity) -> Option<&dyn Any>;
+
+    fn contains(&self, entity: Entity) -> bool;
+
+    /// The sequence number the component of `entity` was first inserted with.
+    fn sequence(&self, entity: Entity) -> Option<u64>;
+
+    fn entities(&self) -> Vec<Entity>;
+
+    fn len(&self) -> usize;
+
+    fn clear_changed(&mut self);
+
+    /// Removes every component, the storage is kept.
+    fn clear(&mut self);
+
+    /// Renames the owning entities through `mapping`, others keep their id.
+    fn remap(&mut self, mapping: &HashMap<Entity, Entity>);
+
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/// The components of type `T` with their change tracking, kept in a [`Storage`].
+pub(crate) struct Column<T> {
+    storage: Box<dyn Storage<T>>,
+    /// Entities whose component was inserted or mutably accessed since the last `clear_changed`.
+    changed: HashSet<Entity>,
+    /// Insertion sequence of each component, kept when the component is overwritten.
+    sequences: HashMap<Entity, u64>,
+}
+
+impl<T: 'static> Column<T> {
+    pub fn new() -> Self {
+        Self {
+            storage: Box::new(HashMap::new()),
+            changed: HashSet::new(),
+            sequences: HashMap::new(),
+        }
+    }
+
+    /// Moves the components into `storage`, which is used from then on.
+    pub fn set_storage(&mut self, mut storage: Box<dyn Storage<T>>) {
+        for entity in self.entities() {
+            if let Some(component) = self.storage.remove(entity) {
+                storage.insert(entity, component);
+            }
+        }
+        self.storage = storage;
+    }
+
+    pub fn dense(&self) -> Option<&DenseStore<T>> {
+        let storage: &dyn Any = self.storage.as_ref();
+        storage.downcast_ref()
+    }
+
+    pub fn insert(&mut self, entity: Entity, component: T, sequence: u64) -> Option<T> {
+        self.changed.insert(entity);
+        self.sequences.entry(entity).or_insert(sequence);
+        self.storage.insert(entity, component)
+    }
+
+    pub fn remove(&mut self, entity: Entity) -> Option<T> {
+        self.changed.remove(&entity);
+        self.sequences.remove(&entity);
+        self.storage.remove(entity)
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        self.storage.get(entity)
+    }
+
+    /// Marks the component as changed.
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        let component = self.storage.get_mut(entity)?;
+        self.changed.insert(entity);
+        Some(component)
+    }
+
+    pub fn is_changed(&self, entity: Entity) -> bool {
+        self.changed.contains(&entity)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> + '_ {
+        self.storage.iter()
+    }
+
+    /// Mutable references to the components of every entity passing `keep`, all marked changed.
+    pub fn iter_mut_where(&mut self, keep: impl Fn(Entity) -> bool) -> Vec<(Entity, &mut T)> {
+        let changed = &mut self.changed;
+        self.storage
+            .iter_mut()
+            .filter(|(entity, _)| keep(*entity))
+            .inspect(|(entity, _)| {
+                changed.insert(*entity);
+            })
+            .collect()
+    }
+}
+
+impl<T: 'static> ComponentColumn for Column<T> {
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<T>()
+    }
+
+    fn insert_boxed(
+        &mut self,
+        entity: Entity,
+        component: Box<dyn Any>,
+        sequence: u64,
+    ) -> Option<Box<dyn Any>> {
+        let component = component.downcast::<T>().ok()?;
+        self.insert(entity, *component, sequence)
+            .map(|previous| Box::new(previous) as Box<dyn Any>)
+    }
+
+    fn remove_boxed(&mut self, entity: Entity) -> Option<Box<dyn Any>> {
+        self.remove(entity)
+            .map(|component| Box::new(component) as Box<dyn Any>)
+    }
+
+    fn get_any(&self, entity: Entity) -> Option<&dyn Any> {
+        self.get(entity).map(|component| component as &dyn Any)
+    }
+
+    fn contains(&self, entity: Entity) -> bool {
+        self.storage.contains(entity)
+    }
+
+    fn sequence(&self, entity: Entity) -> Option<u64> {
+        self.sequences.get(&entity).copied()
+    }
+
+    fn entities(&self) -> Vec<Entity> {
+        self.storage.iter().map(|(entity, _)| entity).collect()
+    }
+
+    fn len(&self) -> usize {
+        self.storage.len()
+    }
+
+    fn clear_changed(&mut self) {
+        self.changed.clear();
+    }
+
+    fn clear(&mut self) {
+        for entity in self.entities() {
+            self.storage.remove(entity);
+        }
+        self.changed.clear();
+        self.sequences.clear();
+    }
+
+    fn remap(&mut self, mapping: &HashMap<Entity, Entity>) {
+        let map = |entity: Entity| mapping.get(&entity).copied().unwrap_or(entity);
+        // Take everything out first so renamed entities never collide with old ones.
+        let components: Vec<(Entity, T)> = self
+            .entities()
+            .into_iter()
+            .filter_map(|entity| Some((map(entity), self.storage.remove(entity)?)))
+            .collect();
+        for (entity, component) in components {
+            self.storage.insert(entity, component);
+        }
+        self.changed = self.changed.iter().copied().map(map).collect();
+        self.sequences = std::mem::take(&mut self.sequences)
+            .into_iter()
+            .map(|(entity, sequence)| (map(entity), sequence))
+            .collect();
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Downcasts a type-erased column to the column of `T`.
+pub(crate) fn typed<T: 'static>(column: &dyn ComponentColumn) -> Option<&Column<T>> {
+    column.as_any().downcast_ref()
+}
+
+pub(crate) fn typed_mut<T: 'static>(column: &mut dyn ComponentColumn) -> Option<&mut Column<T>> {
+    column.as_any_mut().downcast_mut()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sparse_and_dense_behave_alike() {
+        let mut sparse = Column::<i32>::new();
+        let mut dense = Column::<i32>::new();
+        dense.set_storage(Box::new(DenseStore::default()));
+        assert!(sparse.dense().is_none());
+        assert!(dense.dense().is_some());
+
+        for column in [&mut sparse, &mut dense] {
+            assert_eq!(column.insert(Entity(1), 1, 0), None);
+            assert_eq!(column.insert(Entity(2), 2, 1), None);
+            assert_eq!(column.insert(Entity(1), 10, 2), Some(1));
+            assert_eq!(column.sequence(Entity(1)), Some(0));
+            column.clear_changed();
+
+            *column.get_mut(Entity(2)).unwrap() += 1;
+            assert!(column.is_changed(Entity(2)));
+            assert!(!column.is_changed(Entity(1)));
+
+            column.remap(&HashMap::from([(Entity(2), Entity(5))]));
+            assert_eq!(column.get(Entity(5)), Some(&3));
+            assert_eq!(column.sequence(Entity(5)), Some(1));
+            assert!(column.is_changed(Entity(5)));
+            assert_eq!(column.remove(Entity(1)), Some(10));
+            assert_eq!(column.len(), 1);
+        }
+    }
+
+    #[test]
+    fn boxed_access_checks_type() {
+        let mut column = Column::<i32>::new();
+        assert!(column
+            .insert_boxed(Entity(1), Box::new(1.0f32), 0)
+            .is_none());
+        assert!(!column.contains(Entity(1)));
+
+        column.insert_boxed(Entity(1), Box::new(1), 0);
+        assert_eq!(
+            column.get_any(Entity(1)).unwrap().downcast_ref::<i32>(),
+            Some(&1)
+        );
+        assert_eq!(column.type_name(), "i32");
+    }
+}
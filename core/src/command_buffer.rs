@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use crate::{entity::Entity, entity_component_manager::EntityComponentManager};
+
+type DeferredOp = Box<dyn FnOnce(&mut EntityComponentManager, Entity)>;
+
+/// The entity a command applies to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Target {
+    /// An entity that already exists in the manager.
+    Real(Entity),
+    /// An entity spawned by an earlier [`CommandBuffer::spawn`] of the same buffer.
+    Pending(u64),
+}
+
+impl From<Entity> for Target {
+    fn from(entity: Entity) -> Self {
+        Target::Real(entity)
+    }
+}
+
+enum Command {
+    Spawn(u64),
+    Despawn(Target),
+    Insert(Target, DeferredOp),
+    Remove(Target, DeferredOp),
+}
+
+/// Records structural changes so they can be applied after iterating the manager.
+///
+/// [`CommandBuffer::spawn`] returns a [`Target::Pending`] handle. It can be used in
+/// later commands of the same buffer and resolves to a real entity on apply.
+#[derive(Default)]
+pub struct CommandBuffer {
+    commands: Vec<Command>,
+    pending_counter: u64,
+}
+
+impl CommandBuffer {
+    pub fn spawn(&mut self) -> Target {
+        let pending = self.pending_counter;
+        self.pending_counter += 1;
+        self.commands.push(Command::Spawn(pending));
+        Target::Pending(pending)
+    }
+
+    pub fn despawn(&mut self, target: impl Into<Target>) {
+        self.commands.push(Command::Despawn(target.into()));
+    }
+
+    pub fn insert_component<T: 'static>(&mut self, target: impl Into<Target>, component: T) {
+        self.commands.push(Command::Insert(
+            target.into(),
+            Box::new(move |manager, entity| manager.insert_component(entity, component)),
+        ));
+    }
+
+    pub fn remove_component<T: 'static>(&mut self, target: impl Into<Target>) {
+        self.commands.push(Command::Remove(
+            target.into(),
+            Box::new(|manager, entity| manager.remove_component::<T>(entity)),
+        ));
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Replays the recorded commands in order and returns the pending to entity mapping.
+    ///
+    /// Commands on a pending target this buffer never spawned are skipped.
+    pub(crate) fn apply(self, manager: &mut EntityComponentManager) -> HashMap<Target, Entity> {
+        let mut spawned = HashMap::new();
+        let resolve = |spawned: &HashMap<Target, Entity>, target: Target| match target {
+            Target::Real(entity) => Some(entity),
+            Target::Pending(_) => spawned.get(&target).copied(),
+        };
+
+        for command in self.commands {
+            match command {
+                Command::Spawn(pending) => {
+                    spawned.insert(Target::Pending(pending), manager.create_entity());
+                }
+                Command::Despawn(target) => {
+                    if let Some(entity) = resolve(&spawned, target) {
+                        manager.delete_entity(entity);
+                    }
+                }
+                Command::Insert(target, op) | Command::Remove(target, op) => {
+                    if let Some(entity) = resolve(&spawned, target) {
+                        op(manager, entity);
+                    }
+                }
+            }
+        }
+        spawned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn despawn_while_iterating() {
+        let mut manager = EntityComponentManager::default();
+        let first = manager.create_entity();
+        let second = manager.create_entity();
+        let third = manager.create_entity();
+        manager.insert_component(first, 1);
+        manager.insert_component(second, 2);
+        manager.insert_component(third, 1.0f32);
+
+        let mut commands = CommandBuffer::default();
+        for (entity, _) in manager.queue_component::<i32>() {
+            commands.despawn(entity);
+        }
+        assert_eq!(commands.len(), 2);
+
+        manager.apply_commands(commands);
+        assert!(manager.queue_component::<i32>().is_empty());
+        assert_eq!(manager.get_component::<f32>(third), Some(&1.0));
+    }
+
+    #[test]
+    fn spawn_resolves_placeholder() {
+        let mut manager = EntityComponentManager::default();
+        let existing = manager.create_entity();
+        manager.insert_component(existing, 1);
+
+        let mut commands = CommandBuffer::default();
+        let placeholder = commands.spawn();
+        commands.insert_component(placeholder, 2);
+        commands.insert_component(placeholder, 2.0f32);
+        commands.remove_component::<f32>(placeholder);
+        commands.remove_component::<i32>(existing);
+
+        let spawned = manager.apply_commands(commands);
+        let entity = spawned[&placeholder];
+        assert!(manager.is_alive(entity));
+        assert_eq!(manager.get_component::<i32>(entity), Some(&2));
+        assert_eq!(manager.get_component::<f32>(entity), None);
+        assert_eq!(manager.get_component::<i32>(existing), None);
+    }
+
+    #[test]
+    fn pending_targets_never_hit_real_entities() {
+        let mut manager = EntityComponentManager::default();
+        let high = manager.spawn_with_id(Entity(u64::MAX - 1)).unwrap();
+        manager.insert_component(high, 1);
+
+        let mut commands = CommandBuffer::default();
+        let pending = commands.spawn();
+        commands.insert_component(pending, 2);
+        commands.insert_component(high, 3u32);
+        commands.insert_component(Target::Pending(7), 4.0f32);
+
+        let spawned = manager.apply_commands(commands);
+        let entity = spawned[&pending];
+        assert_ne!(entity, high);
+        assert_eq!(manager.get_component::<i32>(high), Some(&1));
+        assert_eq!(manager.get_component::<u32>(high), Some(&3));
+        assert_eq!(manager.get_component::<i32>(entity), Some(&2));
+        assert!(manager.queue_component::<f32>().is_empty());
+    }
+}
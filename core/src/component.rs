@@ -2,4 +2,76 @@ use std::any::Any;
 
 use serde::{Deserialize, Serialize};
 
-pub trait Component: Any + Send + Sync + Serialize + for<'a> Deserialize<'a> {}
+pub trait Component: Any + Send + Sync {}
+
+/// A [`Component`] that opts into world serialization.
+pub trait SerializableComponent: Component + Serialize + for<'a> Deserialize<'a> {}
+
+/// A component that re-inserts combine with the existing value, see
+/// [`EntityComponentManager::insert_or_merge`](crate::entity_component_manager::EntityComponentManager::insert_or_merge).
+pub trait MergeableComponent {
+    fn merge(&mut self, incoming: Self);
+}
+
+/// Small integer assigned to a component type by
+/// [`EntityComponentManager::register_component_id`](crate::entity_component_manager::EntityComponentManager::register_component_id).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ComponentId(pub(crate) u8);
+
+impl ComponentId {
+    /// The bit of this type in an entity's component mask.
+    pub fn mask(self) -> u64 {
+        1 << self.0
+    }
+}
+
+/// Fetches several components of one entity, failing on the first one missing.
+///
+/// Expands to a `Result` of a tuple of references built from
+/// [`EntityComponentManager::get_component_checked`](crate::entity_component_manager::EntityComponentManager::get_component_checked):
+///
+/// ```text
+/// let (position, velocity) = components!(manager, entity, Position, Velocity)?;
+/// ```
+#[macro_export]
+macro_rules! components {
+    ($manager:expr, $entity:expr, $($component:ty),+ $(,)?) => {{
+        let manager = &$manager;
+        let entity = $entity;
+        (|| -> ::std::result::Result<_, $crate::error::EcsError> {
+            Ok(($(manager.get_component_checked::<$component>(entity)?,)+))
+        })()
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{entity_component_manager::EntityComponentManager, error::EcsError};
+
+    #[derive(Debug, PartialEq)]
+    struct Position(i32);
+    #[derive(Debug, PartialEq)]
+    struct Velocity(i32);
+
+    #[test]
+    fn components_macro_fetches_all() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, Position(1));
+        manager.insert_component(entity, Velocity(2));
+
+        let (position, velocity) = components!(manager, entity, Position, Velocity).unwrap();
+        assert_eq!(position, &Position(1));
+        assert_eq!(velocity, &Velocity(2));
+    }
+
+    #[test]
+    fn components_macro_reports_missing() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, Position(1));
+
+        let result = components!(manager, entity, Position, Velocity);
+        assert!(matches!(result, Err(EcsError::ComponentNotFound(e, _)) if e == entity));
+    }
+}
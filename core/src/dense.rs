@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use crate::entity::Entity;
+
+/// Contiguous storage for a single component type.
+///
+/// Components live in one `Vec<T>` so iterating them is a linear scan. Removal
+/// swaps the last component into the freed slot.
+pub struct DenseStore<T> {
+    components: Vec<T>,
+    entities: Vec<Entity>,
+    index: HashMap<Entity, usize>,
+}
+
+impl<T> Default for DenseStore<T> {
+    fn default() -> Self {
+        Self {
+            components: Vec::new(),
+            entities: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+}
+
+impl<T> DenseStore<T> {
+    /// Inserts or replaces the component of `entity`, returning the previous one.
+    pub fn insert(&mut self, entity: Entity, component: T) -> Option<T> {
+        if let Some(&index) = self.index.get(&entity) {
+            return Some(std::mem::replace(&mut self.components[index], component));
+        }
+        self.index.insert(entity, self.components.len());
+        self.components.push(component);
+        self.entities.push(entity);
+        None
+    }
+
+    pub fn remove(&mut self, entity: Entity) -> Option<T> {
+        let index = self.index.remove(&entity)?;
+        let component = self.components.swap_remove(index);
+        self.entities.swap_remove(index);
+        if let Some(&moved) = self.entities.get(index) {
+            self.index.insert(moved, index);
+        }
+        Some(component)
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        self.index
+            .get(&entity)
+            .map(|&index| &self.components[index])
+    }
+
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        self.index
+            .get(&entity)
+            .map(|&index| &mut self.components[index])
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.index.contains_key(&entity)
+    }
+
+    /// The owning entities, in the same order as [`Self::components`].
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+
+    pub fn components(&self) -> &[T] {
+        &self.components
+    }
+
+    pub fn components_mut(&mut self) -> &mut [T] {
+        &mut self.components
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.entities.iter().copied().zip(&self.components)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        self.entities.iter().copied().zip(&mut self.components)
+    }
+
+    pub fn len(&self) -> usize {
+        self.components.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+}
+
+impl<T> IntoIterator for DenseStore<T> {
+    type Item = (Entity, T);
+    type IntoIter = std::iter::Zip<std::vec::IntoIter<Entity>, std::vec::IntoIter<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entities.into_iter().zip(self.components)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remove_keeps_index_in_sync() {
+        let mut store = DenseStore::default();
+        store.insert(Entity(1), 'a');
+        store.insert(Entity(2), 'b');
+        store.insert(Entity(3), 'c');
+        assert_eq!(store.insert(Entity(2), 'B'), Some('b'));
+
+        assert_eq!(store.remove(Entity(1)), Some('a'));
+        assert_eq!(store.get(Entity(3)), Some(&'c'));
+        assert_eq!(store.get(Entity(2)), Some(&'B'));
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.entities().len(), store.components().len());
+        assert_eq!(store.remove(Entity(1)), None);
+    }
+}
@@ -0,0 +1,32 @@
+use std::collections::BTreeMap;
+
+use crate::entity::Entity;
+
+/// Structural differences between two managers, see
+/// [`EntityComponentManager::diff`](crate::entity_component_manager::EntityComponentManager::diff).
+///
+/// Only entities and component types are compared, not component values.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct WorldDiff {
+    /// Entities only alive in the other manager, sorted by id.
+    pub added_entities: Vec<Entity>,
+    /// Entities only alive in this manager, sorted by id.
+    pub removed_entities: Vec<Entity>,
+    /// Component type changes of entities alive in both managers.
+    pub changed_components: BTreeMap<Entity, ComponentChanges>,
+}
+
+impl WorldDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_entities.is_empty()
+            && self.removed_entities.is_empty()
+            && self.changed_components.is_empty()
+    }
+}
+
+/// Component type names gained and lost by one entity, both sorted.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ComponentChanges {
+    pub added: Vec<&'static str>,
+    pub removed: Vec<&'static str>,
+}
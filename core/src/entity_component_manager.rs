@@ -1,195 +1,3975 @@
-use std::{any::Any, collections::HashMap};
+use std::{
+    any::{Any, TypeId},
+    collections::{BTreeSet, HashMap, HashSet},
+};
 
-use crate::{entity::Entity, entity_tree::EntityTree};
+use crate::{
+    bloom::ComponentBloom,
+    builder::EntityBuilder,
+    bundle::Bundle,
+    column::{typed, typed_mut, Column, ComponentColumn},
+    command_buffer::{CommandBuffer, Target},
+    component::{ComponentId, MergeableComponent, SerializableComponent},
+    dense::DenseStore,
+    diff::{ComponentChanges, WorldDiff},
+    entity::Entity,
+    entity_tree::EntityTree,
+    error::{EcsError, FindEntityLocation},
+    query::{Query, QueryState},
+    serialization::{
+        ComponentRecord, ComponentSerializer, EntityData, Migration, WorldData, WorldSnapshot,
+    },
+    storage::ComponentConfig,
+    view::ComponentView,
+};
 
-type ComponentStore = HashMap<(Entity, String), Box<dyn Any>>;
+type ComponentStore = HashMap<TypeId, Box<dyn ComponentColumn>>;
+/// An [`EntityData`] whose components are deserialized but not yet inserted.
+struct DecodedEntity {
+    components: Vec<Box<dyn Any>>,
+    children: Vec<DecodedEntity>,
+}
+
+type ComponentCloner = fn(&dyn Any) -> Option<Box<dyn Any>>;
+type DespawnHook = Box<dyn Fn(Entity)>;
+type ComponentHooks = HashMap<TypeId, Vec<Box<dyn Fn(Entity)>>>;
+type ComponentValidator = Box<dyn Fn(&dyn Any) -> Result<(), String>>;
+
+/// Order in which [`EntityComponentManager::despawn_all`] deletes entities.
+///
+/// Entity ids are handed out in increasing order, so spawn order is id order.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum DespawnOrder {
+    SpawnOrder,
+    /// Newest entities first, so dependents die before their dependencies.
+    #[default]
+    ReverseSpawnOrder,
+}
+
+pub struct EntityComponentManager {
+    component_store: ComponentStore,
+    entites: EntityTree,
+    entity_counter: Entity,
+    cloners: HashMap<&'static str, ComponentCloner>,
+    names: HashMap<Entity, String>,
+    entities_by_name: HashMap<String, Entity>,
+    disabled: HashSet<Entity>,
+    despawn_hooks: Vec<DespawnHook>,
+    serializers: HashMap<&'static str, ComponentSerializer>,
+    reserved: Vec<Entity>,
+    insert_hooks: ComponentHooks,
+    remove_hooks: ComponentHooks,
+    blooms: HashMap<Entity, ComponentBloom>,
+    validators: HashMap<TypeId, ComponentValidator>,
+    /// Zero-sized marker components, stored as sets instead of columns.
+    tags: HashMap<TypeId, HashSet<Entity>>,
+    component_ids: HashMap<TypeId, ComponentId>,
+    /// Bits of the [`ComponentId`] types each entity has.
+    masks: HashMap<Entity, u64>,
+    /// Sequence number handed to the next inserted component.
+    next_sequence: u64,
+    /// Ids start after this value, see [`Self::with_id_offset`].
+    id_offset: u64,
+}
+
+impl Default for EntityComponentManager {
+    fn default() -> Self {
+        Self {
+            component_store: HashMap::new(),
+            entites: EntityTree::default(),
+            entity_counter: Entity(0),
+            cloners: HashMap::new(),
+            names: HashMap::new(),
+            entities_by_name: HashMap::new(),
+            disabled: HashSet::new(),
+            despawn_hooks: Vec::new(),
+            serializers: HashMap::new(),
+            reserved: Vec::new(),
+            insert_hooks: HashMap::new(),
+            remove_hooks: HashMap::new(),
+            blooms: HashMap::new(),
+            validators: HashMap::new(),
+            tags: HashMap::new(),
+            component_ids: HashMap::new(),
+            masks: HashMap::new(),
+            next_sequence: 0,
+            id_offset: 0,
+        }
+    }
+}
+
+/// Borrows the `W` column mutably and the `R` column shared, `None` if they are the same type.
+fn split_columns<W: 'static, R: 'static>(
+    store: &mut ComponentStore,
+) -> Option<(&mut Column<W>, &Column<R>)> {
+    if TypeId::of::<W>() == TypeId::of::<R>() {
+        return None;
+    }
+    let (mut writes, mut reads) = (None, None);
+    for (type_id, column) in store.iter_mut() {
+        if *type_id == TypeId::of::<W>() {
+            writes = typed_mut::<W>(&mut **column);
+        } else if *type_id == TypeId::of::<R>() {
+            reads = typed::<R>(&**column);
+        }
+    }
+    Some((writes?, reads?))
+}
+
+fn clone_component<T: Clone + 'static>(component: &dyn Any) -> Option<Box<dyn Any>> {
+    component
+        .downcast_ref::<T>()
+        .map(|component| Box::new(component.clone()) as Box<dyn Any>)
+}
+
+// Every change to the component store goes through these so the caches stay in sync.
+impl EntityComponentManager {
+    fn column<T: 'static>(&self) -> Option<&Column<T>> {
+        typed(self.component_store.get(&TypeId::of::<T>())?.as_ref())
+    }
+
+    fn column_mut<T: 'static>(&mut self) -> Option<&mut Column<T>> {
+        typed_mut(self.component_store.get_mut(&TypeId::of::<T>())?.as_mut())
+    }
+
+    fn column_or_default<T: 'static>(&mut self) -> &mut Column<T> {
+        let column = self
+            .component_store
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(Column::<T>::new()));
+        typed_mut(column.as_mut()).expect("columns are keyed by their component TypeId")
+    }
+
+    fn store_insert_typed<T: 'static>(&mut self, entity: Entity, component: T) -> Option<T> {
+        if !self.can_own_components(entity) {
+            return None;
+        }
+        let sequence = self.take_sequence();
+        let previous = self
+            .column_or_default::<T>()
+            .insert(entity, component, sequence);
+        self.inserted(entity, TypeId::of::<T>(), previous.is_none());
+        previous
+    }
+
+    /// Inserts a type-erased component into the existing column of its type.
+    ///
+    /// Callers must have created the column, e.g. through [`Self::register_component`].
+    fn store_insert(&mut self, entity: Entity, component: Box<dyn Any>) -> Option<Box<dyn Any>> {
+        let type_id = (*component).type_id();
+        debug_assert!(
+            self.component_store.contains_key(&type_id),
+            "no column for a type-erased component"
+        );
+        if !self.can_own_components(entity) {
+            return None;
+        }
+        let sequence = self.take_sequence();
+        let previous = self
+            .component_store
+            .get_mut(&type_id)?
+            .insert_boxed(entity, component, sequence);
+        self.inserted(entity, type_id, previous.is_none());
+        previous
+    }
+
+    fn take_sequence(&mut self) -> u64 {
+        self.next_sequence += 1;
+        self.next_sequence
+    }
+
+    /// Only created or reserved entities own components, never `Entity::none()`.
+    fn can_own_components(&self, entity: Entity) -> bool {
+        self.is_alive(entity) || self.reserved.contains(&entity)
+    }
+
+    fn inserted(&mut self, entity: Entity, type_id: TypeId, is_new: bool) {
+        if is_new {
+            self.blooms.entry(entity).or_default().insert(type_id);
+            if let Some(id) = self.component_ids.get(&type_id) {
+                *self.masks.entry(entity).or_default() |= id.mask();
+            }
+        }
+        for hook in self.insert_hooks.get(&type_id).into_iter().flatten() {
+            hook(entity);
+        }
+    }
+
+    fn store_remove_typed<T: 'static>(&mut self, entity: Entity) -> Option<T> {
+        let removed = self.column_mut::<T>()?.remove(entity)?;
+        self.removed(entity, TypeId::of::<T>());
+        Some(removed)
+    }
+
+    fn store_remove(&mut self, entity: Entity, type_id: TypeId) -> Option<Box<dyn Any>> {
+        let removed = self
+            .component_store
+            .get_mut(&type_id)?
+            .remove_boxed(entity)?;
+        self.removed(entity, type_id);
+        Some(removed)
+    }
+
+    fn removed(&mut self, entity: Entity, type_id: TypeId) {
+        if let Some(bloom) = self.blooms.get_mut(&entity) {
+            bloom.remove(type_id);
+        }
+        if let (Some(id), Some(mask)) = (
+            self.component_ids.get(&type_id),
+            self.masks.get_mut(&entity),
+        ) {
+            *mask &= !id.mask();
+        }
+        for hook in self.remove_hooks.get(&type_id).into_iter().flatten() {
+            hook(entity);
+        }
+    }
+
+    /// Cheap pre-check before a store lookup, `false` means `T` is definitely absent.
+    fn may_have<T: 'static>(&self, entity: Entity) -> bool {
+        self.blooms
+            .get(&entity)
+            .is_some_and(|bloom| bloom.may_contain(TypeId::of::<T>()))
+    }
+
+    fn store_remove_where(&mut self, pred: impl Fn(Entity, TypeId) -> bool) {
+        let keys: Vec<_> = self
+            .component_store
+            .iter()
+            .flat_map(|(type_id, column)| {
+                column
+                    .entities()
+                    .into_iter()
+                    .map(move |entity| (entity, *type_id))
+            })
+            .filter(|(entity, type_id)| pred(*entity, *type_id))
+            .collect();
+        for (entity, type_id) in keys {
+            self.store_remove(entity, type_id);
+        }
+    }
+
+    fn remove_all_components(&mut self, entity: Entity) {
+        let type_ids: Vec<TypeId> = self
+            .component_store
+            .iter()
+            .filter(|(_, column)| column.contains(entity))
+            .map(|(type_id, _)| *type_id)
+            .collect();
+        for type_id in type_ids {
+            self.store_remove(entity, type_id);
+        }
+    }
+}
+
+impl EntityComponentManager {
+    /// Number of entities in the tree, reserved entities are not counted.
+    pub fn len(&self) -> usize {
+        self.entites.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entites.is_empty() && self.reserved.is_empty()
+    }
+
+    /// Removes all entities and components and restarts ids after the id offset, at
+    /// `Entity(1)` by default.
+    ///
+    /// Registrations, hooks and storage configurations are kept. No hooks fire.
+    pub fn clear(&mut self) {
+        for column in self.component_store.values_mut() {
+            column.clear();
+        }
+        self.entites = EntityTree::default();
+        self.entity_counter = Entity(self.id_offset);
+        self.names.clear();
+        self.entities_by_name.clear();
+        self.disabled.clear();
+        self.reserved.clear();
+        self.blooms.clear();
+        self.tags.clear();
+        self.masks.clear();
+        self.next_sequence = 0;
+    }
+
+    /// Starts building an entity, see [`EntityBuilder`].
+    pub fn spawn(&mut self) -> EntityBuilder<'_> {
+        EntityBuilder::new(self)
+    }
+
+    /// A manager whose first entity is `Entity(start + 1)`.
+    ///
+    /// Lets several managers allocate from disjoint id ranges.
+    pub fn with_id_offset(start: u64) -> Self {
+        Self {
+            entity_counter: Entity(start),
+            id_offset: start,
+            ..Self::default()
+        }
+    }
+
+    /// The entity the next [`Self::create_entity`] call will return.
+    pub fn next_entity_id(&self) -> Entity {
+        Entity(self.entity_counter.0 + 1)
+    }
+
+    pub fn create_entity(&mut self) -> Entity {
+        let entity = self.allocate_entity();
+        self.insert_entity_node(entity);
+        entity
+    }
+
+    fn allocate_entity(&mut self) -> Entity {
+        self.entity_counter.0 += 1;
+        self.entity_counter
+    }
+
+    fn insert_entity_node(&mut self, entity: Entity) {
+        self.entites.insert_node(entity);
+        if self.entites.root.is_none() {
+            self.entites.set_root(entity);
+        }
+    }
+
+    /// Allocates a new entity id without adding it to the tree yet.
+    ///
+    /// The entity is not alive until [`Self::flush_reserved`] is called.
+    pub fn reserve_entity(&mut self) -> Entity {
+        let entity = self.allocate_entity();
+        self.reserved.push(entity);
+        entity
+    }
+
+    /// Adds all entities from [`Self::reserve_entity`] to the tree.
+    pub fn flush_reserved(&mut self) {
+        for entity in std::mem::take(&mut self.reserved) {
+            self.insert_entity_node(entity);
+        }
+    }
+
+    /// Makes `entity` the root, detaching it from its parent if it has one.
+    pub fn set_root(&mut self, entity: Entity) {
+        self.entites.set_root(entity);
+    }
+
+    pub fn root(&self) -> Option<Entity> {
+        self.entites.root
+    }
+
+    /// Returns whether `entity` was created and has not been deleted since.
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        entity.is_some() && self.entites.contains(entity)
+    }
+
+    /// Despawn hooks only fire if `entity` was in the tree.
+    ///
+    /// A reserved entity that is not flushed yet is dropped and never added to the tree.
+    pub fn delete_entity(&mut self, entity: Entity) {
+        let existed = self.entites.contains(entity);
+        self.reserved.retain(|reserved| *reserved != entity);
+        self.entites.remove(entity);
+        self.remove_all_components(entity);
+        self.forget_entity(entity);
+        if existed {
+            self.fire_despawn_hooks(entity);
+        }
+    }
+
+    /// Removes every component and tag of `entity`, it stays alive in the tree with its children.
+    pub fn clear_entity_components(&mut self, entity: Entity) {
+        self.remove_all_components(entity);
+        for tagged in self.tags.values_mut() {
+            tagged.remove(&entity);
+        }
+    }
+
+    /// Drops the per-entity bookkeeping of a deleted entity.
+    fn forget_entity(&mut self, entity: Entity) {
+        self.blooms.remove(&entity);
+        self.masks.remove(&entity);
+        if let Some(name) = self.names.remove(&entity) {
+            self.entities_by_name.remove(&name);
+        }
+        self.disabled.remove(&entity);
+        for tagged in self.tags.values_mut() {
+            tagged.remove(&entity);
+        }
+    }
+
+    fn fire_despawn_hooks(&self, entity: Entity) {
+        for hook in &self.despawn_hooks {
+            hook(entity);
+        }
+    }
+
+    /// Merges `component` into the `T` of `entity`, or inserts it if there is none.
+    ///
+    /// A merge marks the component changed but does not run insert hooks.
+    pub fn insert_or_merge<T: MergeableComponent + 'static>(
+        &mut self,
+        entity: Entity,
+        component: T,
+    ) {
+        match self.get_component_mut::<T>(entity) {
+            Some(existing) => existing.merge(component),
+            None => self.insert_component(entity, component),
+        }
+    }
+
+    /// Attaches the marker `T` to `entity` without storing a value.
+    pub fn insert_tag<T: 'static>(&mut self, entity: Entity) {
+        if self.can_own_components(entity) {
+            self.tags
+                .entry(TypeId::of::<T>())
+                .or_default()
+                .insert(entity);
+        }
+    }
+
+    pub fn remove_tag<T: 'static>(&mut self, entity: Entity) {
+        if let Some(tagged) = self.tags.get_mut(&TypeId::of::<T>()) {
+            tagged.remove(&entity);
+        }
+    }
+
+    pub fn has_tag<T: 'static>(&self, entity: Entity) -> bool {
+        self.tags
+            .get(&TypeId::of::<T>())
+            .is_some_and(|tagged| tagged.contains(&entity))
+    }
+
+    /// Enabled entities tagged with `T`, sorted by id.
+    pub fn query_tag<T: 'static>(&self) -> Vec<Entity> {
+        let mut entities: Vec<Entity> = self
+            .tags
+            .get(&TypeId::of::<T>())
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|entity| self.is_queryable(*entity))
+            .collect();
+        entities.sort_unstable();
+        entities
+    }
+
+    /// Inserts or overwrites the `T` component of `entity`.
+    ///
+    /// Ignored for `Entity::none()` and entities that were never created or are deleted.
+    pub fn insert_component<T: 'static>(&mut self, entity: Entity, component: T) {
+        self.store_insert_typed(entity, component);
+    }
+
+    pub fn get_component<T: 'static>(&self, entity: Entity) -> Option<&T> {
+        if entity.is_none() || !self.may_have::<T>(entity) {
+            return None;
+        }
+        self.column::<T>()?.get(entity)
+    }
+
+    /// Returns a copy of the `T` component so the manager is not kept borrowed.
+    pub fn get_component_copy<T: Copy + 'static>(&self, entity: Entity) -> Option<T> {
+        self.get_component::<T>(entity).copied()
+    }
+
+    /// Like [`Self::insert_component`], but returns the component it replaced.
+    pub fn replace_component<T: 'static>(&mut self, entity: Entity, component: T) -> Option<T> {
+        self.store_insert_typed(entity, component)
+    }
+
+    /// Like [`Self::get_component`], but tells a dead entity apart from a missing component.
+    pub fn get_component_checked<T: 'static>(&self, entity: Entity) -> Result<&T, EcsError> {
+        if !self.is_alive(entity) {
+            return Err(EcsError::EntityNotFound(
+                entity,
+                FindEntityLocation::ComponentManager,
+            ));
+        }
+        self.get_component(entity)
+            .ok_or(EcsError::ComponentNotFound(
+                entity,
+                std::any::type_name::<T>(),
+            ))
+    }
+
+    /// Like [`Self::get_component_checked`], but also reports a stored component that
+    /// fails to downcast to `T` as `ComponentTypeMismatch`.
+    ///
+    /// Columns are keyed by `TypeId`, so a mismatch points at a broken store.
+    pub fn get_component_or_err<T: 'static>(&self, entity: Entity) -> Result<&T, EcsError> {
+        let column = self.component_store.get(&TypeId::of::<T>());
+        if column
+            .is_some_and(|column| column.contains(entity) && typed::<T>(column.as_ref()).is_none())
+        {
+            return Err(EcsError::ComponentTypeMismatch(
+                entity,
+                std::any::type_name::<T>(),
+            ));
+        }
+        self.get_component_checked(entity)
+    }
+
+    pub fn get_component_mut<T: 'static>(&mut self, entity: Entity) -> Option<&mut T> {
+        if !self.may_have::<T>(entity) {
+            return None;
+        }
+        self.column_mut::<T>()?.get_mut(entity)
+    }
+
+    pub fn remove_component<T: 'static>(&mut self, entity: Entity) {
+        self.store_remove_typed::<T>(entity);
+    }
+
+    /// Returns every `T` component of enabled entities.
+    ///
+    /// Never panics, every column holds a single type so no entry is downcast.
+    pub fn queue_component<T: 'static>(&self) -> Vec<(Entity, &T)> {
+        self.iter_component().collect()
+    }
+
+    /// Lazy counterpart of [`Self::queue_component`], nothing is collected.
+    pub fn iter_component<T: 'static>(&self) -> impl Iterator<Item = (Entity, &T)> + '_ {
+        self.column::<T>()
+            .into_iter()
+            .flat_map(Column::iter)
+            .filter(|(entity, _)| self.is_queryable(*entity))
+    }
+
+    /// Like [`Self::queue_component`], but in the order the components were first inserted.
+    ///
+    /// Overwriting a component keeps its position, removing and re-inserting moves it last.
+    pub fn query_component_ordered<T: 'static>(&self) -> Vec<(Entity, &T)> {
+        let Some(column) = self.column::<T>() else {
+            return Vec::new();
+        };
+        let mut components: Vec<_> = self.iter_component().collect();
+        components.sort_by_key(|(entity, _)| column.sequence(*entity));
+        components
+    }
+
+    /// Like [`Self::queue_component`], but sorted by entity id for deterministic iteration.
+    pub fn query_component_sorted<T: 'static>(&self) -> Vec<(Entity, &T)> {
+        let mut components = self.queue_component::<T>();
+        components.sort_unstable_by_key(|(entity, _)| *entity);
+        components
+    }
+
+    /// Replays a [`CommandBuffer`] recorded while the manager was borrowed.
+    ///
+    /// Returns the mapping from the buffer's pending targets to the spawned entities.
+    pub fn apply_commands(&mut self, buffer: CommandBuffer) -> HashMap<Target, Entity> {
+        buffer.apply(self)
+    }
+
+    /// Registers `T` as cloneable so it is copied by [`Self::clone_entity`].
+    pub fn register_cloneable<T: Clone + 'static>(&mut self) {
+        self.cloners
+            .insert(std::any::type_name::<T>(), clone_component::<T>);
+    }
+
+    /// Creates a new entity holding a deep copy of every component of `source`.
+    ///
+    /// Only components whose type was registered with [`Self::register_cloneable`]
+    /// are copied, all others are skipped. Returns `None` if `source` does not exist.
+    pub fn clone_entity(&mut self, source: Entity) -> Option<Entity> {
+        if !self.is_alive(source) {
+            return None;
+        }
+
+        let components: Vec<Box<dyn Any>> = self
+            .component_store
+            .values()
+            .filter_map(|column| {
+                let cloner = self.cloners.get(column.type_name())?;
+                cloner(column.get_any(source)?)
+            })
+            .collect();
+
+        let entity = self.create_entity();
+        for component in components {
+            self.store_insert(entity, component);
+        }
+        Some(entity)
+    }
+
+    /// Names `entity`. Names are unique, an entity already using `name` loses it.
+    pub fn set_name(&mut self, entity: Entity, name: impl Into<String>) {
+        let name = name.into();
+        if let Some(previous) = self.names.remove(&entity) {
+            self.entities_by_name.remove(&previous);
+        }
+        if let Some(owner) = self.entities_by_name.insert(name.clone(), entity) {
+            self.names.remove(&owner);
+        }
+        self.names.insert(entity, name);
+    }
+
+    pub fn name(&self, entity: Entity) -> Option<&str> {
+        self.names.get(&entity).map(String::as_str)
+    }
+
+    pub fn entity_by_name(&self, name: &str) -> Option<Entity> {
+        self.entities_by_name.get(name).copied()
+    }
+
+    /// Like [`Self::queue_component`], but also yields the name of each entity if it has one.
+    pub fn query_named<T: 'static>(&self) -> Vec<(Entity, Option<&str>, &T)> {
+        self.queue_component::<T>()
+            .into_iter()
+            .map(|(entity, component)| (entity, self.name(entity), component))
+            .collect()
+    }
+
+    /// Removes the `T` component of `entity` and returns it.
+    pub fn take_component<T: 'static>(&mut self, entity: Entity) -> Option<T> {
+        self.store_remove_typed(entity)
+    }
+
+    /// Exchanges the `T` components of `a` and `b`.
+    ///
+    /// If only one of them has a `T` it is moved to the other, if neither has one
+    /// nothing happens.
+    pub fn swap_component<T: 'static>(&mut self, a: Entity, b: Entity) {
+        let component_a = self.take_component::<T>(a);
+        let component_b = self.take_component::<T>(b);
+        if let Some(component) = component_a {
+            self.insert_component(b, component);
+        }
+        if let Some(component) = component_b {
+            self.insert_component(a, component);
+        }
+    }
+
+    /// Returns the type name and a type-erased reference of every component on `entity`.
+    pub fn components_of(&self, entity: Entity) -> Vec<(&'static str, &dyn Any)> {
+        self.component_store
+            .values()
+            .filter_map(|column| Some((column.type_name(), column.get_any(entity)?)))
+            .collect()
+    }
+
+    /// Inserts every `(entity, component)` pair of `iter`.
+    pub fn extend_components<T: 'static, I: IntoIterator<Item = (Entity, T)>>(&mut self, iter: I) {
+        for (entity, component) in iter {
+            self.insert_component(entity, component);
+        }
+    }
+
+    /// Removes every `T` component from all entities.
+    pub fn clear_components<T: 'static>(&mut self) {
+        let type_id = TypeId::of::<T>();
+        self.store_remove_where(|_, t| t == type_id);
+    }
+
+    pub fn count_components<T: 'static>(&self) -> usize {
+        self.component_store
+            .get(&TypeId::of::<T>())
+            .map_or(0, |column| column.len())
+    }
+
+    /// Number of entities [`Self::queue_component`] would return, without collecting them.
+    pub fn count_with<T: 'static>(&self) -> usize {
+        self.column::<T>().map_or(0, |column| {
+            column
+                .iter()
+                .filter(|(entity, _)| self.is_queryable(*entity))
+                .count()
+        })
+    }
+
+    /// Whether any queryable entity has a `T`, stops at the first match.
+    pub fn any_with<T: 'static>(&self) -> bool {
+        self.column::<T>()
+            .is_some_and(|column| column.iter().any(|(entity, _)| self.is_queryable(entity)))
+    }
+
+    /// Number of distinct component types currently stored.
+    pub fn component_type_count(&self) -> usize {
+        self.component_store
+            .values()
+            .filter(|column| column.len() > 0)
+            .count()
+    }
+
+    pub fn any_with_component<T: 'static>(&self) -> bool {
+        self.count_components::<T>() > 0
+    }
+
+    /// Calls `f` with the type name and a type-erased reference of every component on `entity`.
+    pub fn for_each_component_of<F: FnMut(&str, &dyn Any)>(&self, entity: Entity, mut f: F) {
+        for column in self.component_store.values() {
+            if let Some(component) = column.get_any(entity) {
+                f(column.type_name(), component);
+            }
+        }
+    }
+
+    /// Disabled entities keep their components but are excluded from queries.
+    pub fn set_enabled(&mut self, entity: Entity, enabled: bool) {
+        if enabled {
+            self.disabled.remove(&entity);
+        } else {
+            self.disabled.insert(entity);
+        }
+    }
+
+    pub fn is_enabled(&self, entity: Entity) -> bool {
+        !self.disabled.contains(&entity)
+    }
+
+    fn is_queryable(&self, entity: Entity) -> bool {
+        self.is_enabled(entity) && !self.entites.is_marked(entity)
+    }
+
+    /// Applies [`Self::set_enabled`] to `entity` and its whole subtree.
+    pub fn set_enabled_recursive(&mut self, entity: Entity, enabled: bool) {
+        let mut stack = vec![entity];
+        while let Some(current) = stack.pop() {
+            self.set_enabled(current, enabled);
+            if let Some(children) = self.entites.children.get(&current) {
+                stack.extend(children);
+            }
+        }
+    }
+
+    /// Returns every entity matching the tuple query `Q` together with its components.
+    pub fn query<Q: Query>(&self) -> Vec<Q::Item<'_>> {
+        let mut candidates = Vec::new();
+        Q::candidates(self, &mut candidates);
+        candidates
+            .into_iter()
+            .filter_map(|entity| Q::fetch(self, entity))
+            .collect()
+    }
+
+    /// Prepares `Q` for repeated execution, see [`QueryState`].
+    pub fn build_query<Q: Query>(&self) -> QueryState<Q> {
+        QueryState::new()
+    }
+
+    /// Returns the first entity holding both `A` and `B` for which `pred` holds.
+    ///
+    /// Stops at the first match instead of building the full join.
+    pub fn find_two<A: 'static, B: 'static, P: Fn(&A, &B) -> bool>(
+        &self,
+        pred: P,
+    ) -> Option<(Entity, &A, &B)> {
+        self.column::<A>()?
+            .iter()
+            .filter(|(entity, _)| self.is_queryable(*entity))
+            .filter_map(|(entity, a)| Some((entity, a, self.get_component::<B>(entity)?)))
+            .find(|(_, a, b)| pred(a, b))
+    }
+
+    /// Registers a hook called with every entity deleted by [`Self::delete_entity`].
+    pub fn on_despawn(&mut self, hook: impl Fn(Entity) + 'static) {
+        self.despawn_hooks.push(Box::new(hook));
+    }
+
+    /// Deletes every entity, firing despawn hooks in the given `order`.
+    pub fn despawn_all(&mut self, order: DespawnOrder) {
+        let mut entities: Vec<Entity> = self.entites.children.keys().copied().collect();
+        if order == DespawnOrder::ReverseSpawnOrder {
+            entities.reverse();
+        }
+        for entity in entities {
+            self.delete_entity(entity);
+        }
+    }
+
+    /// Deletes every entity for which `pred` returns `false`, together with its components.
+    ///
+    /// Surviving children of a deleted parent keep their own subtrees and become
+    /// top-level roots of the tree.
+    pub fn retain_entities<F: Fn(Entity) -> bool>(&mut self, pred: F) {
+        let (keep, doomed): (Vec<Entity>, Vec<Entity>) = self
+            .entites
+            .children
+            .keys()
+            .copied()
+            .partition(|e| pred(*e));
+        let orphans: Vec<Entity> = keep
+            .into_iter()
+            .filter(|entity| {
+                self.entites
+                    .get_parent(*entity)
+                    .is_ok_and(|parent| !pred(*parent))
+            })
+            .collect();
+
+        for entity in doomed {
+            self.delete_entity(entity);
+        }
+        for orphan in orphans {
+            self.entites.add_root(orphan);
+        }
+    }
+
+    /// Assigns `T` a bit in the per-entity component masks, see [`Self::matches_mask`].
+    ///
+    /// Registering the same type again returns its existing id.
+    ///
+    /// # Panics
+    ///
+    /// Panics if more than 64 types are registered.
+    pub fn register_component_id<T: 'static>(&mut self) -> ComponentId {
+        if let Some(id) = self.component_ids.get(&TypeId::of::<T>()) {
+            return *id;
+        }
+        let index = u8::try_from(self.component_ids.len())
+            .ok()
+            .filter(|index| *index < 64)
+            .expect("at most 64 component types can be registered for masks");
+        let id = ComponentId(index);
+        self.component_ids.insert(TypeId::of::<T>(), id);
+
+        let owners: Vec<Entity> = self
+            .component_store
+            .get(&TypeId::of::<T>())
+            .map(|column| column.entities())
+            .unwrap_or_default();
+        for entity in owners {
+            *self.masks.entry(entity).or_default() |= id.mask();
+        }
+        id
+    }
+
+    /// Whether `entity` has every registered type whose bit is set in `mask`.
+    ///
+    /// Build `mask` by or-ing [`ComponentId::mask`] of the wanted types.
+    pub fn matches_mask(&self, entity: Entity, mask: u64) -> bool {
+        self.masks.get(&entity).copied().unwrap_or_default() & mask == mask
+    }
+
+    pub fn has_component<T: 'static>(&self, entity: Entity) -> bool {
+        self.may_have::<T>(entity)
+            && self
+                .column::<T>()
+                .is_some_and(|column| column.contains(entity))
+    }
+
+    /// Returns the `T` components of all entities that also have a `W` component.
+    pub fn query_with<T: 'static, W: 'static>(&self) -> Vec<(Entity, &T)> {
+        self.queue_component::<T>()
+            .into_iter()
+            .filter(|(entity, _)| self.has_component::<W>(*entity))
+            .collect()
+    }
+
+    /// Returns the `T` components of all entities whose `U` component satisfies `pred`.
+    pub fn query_filtered<T: 'static, U: 'static, F: Fn(&U) -> bool>(
+        &self,
+        pred: F,
+    ) -> Vec<(Entity, &T)> {
+        self.queue_component::<T>()
+            .into_iter()
+            .filter(|(entity, _)| self.get_component::<U>(*entity).is_some_and(&pred))
+            .collect()
+    }
+
+    /// Like [`Self::queue_component`], but also yields the parent of each entity.
+    pub fn query_with_parent<T: 'static>(&self) -> Vec<(Entity, Option<Entity>, &T)> {
+        self.queue_component::<T>()
+            .into_iter()
+            .map(|(entity, component)| (entity, self.get_parent(entity).ok().copied(), component))
+            .collect()
+    }
+
+    /// Returns the `T` components of all entities that don't have a `W` component.
+    pub fn query_without<T: 'static, W: 'static>(&self) -> Vec<(Entity, &T)> {
+        self.queue_component::<T>()
+            .into_iter()
+            .filter(|(entity, _)| !self.has_component::<W>(*entity))
+            .collect()
+    }
+
+    /// Creates a new entity as the last child of `parent`.
+    pub fn create_child_entity(&mut self, parent: Entity) -> Result<Entity, EcsError> {
+        if !self.is_alive(parent) {
+            return Err(EcsError::EntityNotFound(
+                parent,
+                FindEntityLocation::EntityTree,
+            ));
+        }
+        let child = self.create_entity();
+        self.entites.add_child(parent, child)?;
+        Ok(child)
+    }
+
+    pub fn get_children(&self, entity: Entity) -> Result<&Vec<Entity>, EcsError> {
+        self.entites.get_children(entity)
+    }
+
+    pub fn get_parent(&self, entity: Entity) -> Result<&Entity, EcsError> {
+        self.entites.get_parent(entity)
+    }
+
+    pub fn child_count(&self, entity: Entity) -> usize {
+        self.entites.child_count(entity)
+    }
+
+    pub fn has_children(&self, entity: Entity) -> bool {
+        self.entites.has_children(entity)
+    }
+
+    /// Registers `T` for world serialization with the given schema `version`.
+    ///
+    /// Only registered types are written by [`Self::serialize_world`], components of
+    /// any other type are skipped. Records written with an older version are upgraded
+    /// with the migrations from [`Self::register_migration`] when loaded.
+    pub fn register_component<T: SerializableComponent>(&mut self, version: u32) {
+        // Loading inserts type-erased components, which needs the column to exist.
+        self.column_or_default::<T>();
+        self.serializers.insert(
+            std::any::type_name::<T>(),
+            ComponentSerializer::of::<T>(version),
+        );
+    }
+
+    /// Registers a migration upgrading `T` records from `from_version` to `from_version + 1`.
+    pub fn register_migration<T: SerializableComponent>(
+        &mut self,
+        from_version: u32,
+        migration: Migration,
+    ) -> Result<(), EcsError> {
+        let type_name = std::any::type_name::<T>();
+        let serializer = self.serializers.get_mut(type_name).ok_or_else(|| {
+            EcsError::Serialization(format!("unregistered component type {}", type_name))
+        })?;
+        serializer.migrations.insert(from_version, migration);
+        Ok(())
+    }
+
+    /// Writes the tree, the entity counter and all registered components to JSON.
+    pub fn serialize_world(&self) -> Result<String, EcsError> {
+        serde_json::to_string(&self.world_data()?)
+            .map_err(|err| EcsError::Serialization(err.to_string()))
+    }
+
+    /// Replaces all entities and components with the world stored in `data`.
+    ///
+    /// Registrations and hooks are kept, tags are cleared. Fails without touching the
+    /// current world if `data` contains a component type that is not registered.
+    pub fn deserialize_world(&mut self, data: &str) -> Result<(), EcsError> {
+        let world: WorldData =
+            serde_json::from_str(data).map_err(|err| EcsError::Serialization(err.to_string()))?;
+        self.load_world_data(world)
+    }
+
+    /// Captures the tree, the entity counter, all registered components and all tags.
+    pub fn snapshot(&self) -> Result<WorldSnapshot, EcsError> {
+        Ok(WorldSnapshot {
+            data: self.world_data()?,
+            tags: self.tags.clone(),
+        })
+    }
+
+    /// Overwrites the whole world with a previously taken [`WorldSnapshot`].
+    pub fn restore(&mut self, snapshot: &WorldSnapshot) -> Result<(), EcsError> {
+        self.load_world_data(snapshot.data.clone())?;
+        self.tags = snapshot.tags.clone();
+        Ok(())
+    }
+
+    fn world_data(&self) -> Result<WorldData, EcsError> {
+        let mut components = Vec::new();
+        for column in self.component_store.values() {
+            let Some(serializer) = self.serializers.get(column.type_name()) else {
+                continue;
+            };
+            for entity in column.entities() {
+                if let Some(component) = column.get_any(entity) {
+                    components.push(ComponentRecord {
+                        entity,
+                        type_name: column.type_name().to_string(),
+                        version: serializer.version,
+                        data: (serializer.serialize)(component)?,
+                        sequence: column.sequence(entity).unwrap_or_default(),
+                    });
+                }
+            }
+        }
+        components.sort_by_key(|record| record.sequence);
+
+        Ok(WorldData {
+            entity_counter: self.entity_counter,
+            tree: self.entites.clone(),
+            names: self
+                .names
+                .iter()
+                .map(|(entity, name)| (*entity, name.clone()))
+                .collect(),
+            disabled: self.disabled.iter().copied().collect(),
+            components,
+        })
+    }
+
+    fn load_world_data(&mut self, mut world: WorldData) -> Result<(), EcsError> {
+        // Re-inserting in the saved order reproduces the insertion order.
+        world.components.sort_by_key(|record| record.sequence);
+        let mut components = Vec::with_capacity(world.components.len());
+        for record in world.components {
+            let entity = record.entity;
+            components.push((entity, self.decode_record(record)?));
+        }
+
+        self.store_remove_where(|_, _| true);
+        self.entites = world.tree;
+        self.entity_counter = world.entity_counter;
+        // Reserved ids belong to the replaced world and would be handed out again.
+        self.reserved.clear();
+        // Tags are not serialized, `restore` puts back the ones of its snapshot.
+        self.tags.clear();
+        self.names = world.names.into_iter().collect();
+        self.entities_by_name = self
+            .names
+            .iter()
+            .map(|(entity, name)| (name.clone(), *entity))
+            .collect();
+        self.disabled = world.disabled.into_iter().collect();
+        for (entity, component) in components {
+            self.store_insert(entity, component);
+        }
+        Ok(())
+    }
+
+    /// Migrates and deserializes `record`, failing for unregistered component types.
+    fn decode_record(&self, record: ComponentRecord) -> Result<Box<dyn Any>, EcsError> {
+        let serializer = self
+            .serializers
+            .get(record.type_name.as_str())
+            .ok_or_else(|| {
+                EcsError::Serialization(format!("unregistered component type {}", record.type_name))
+            })?;
+        let data = serializer.migrate(record)?;
+        (serializer.deserialize)(data)
+    }
+
+    /// Writes the registered components of `entity` to JSON, with its descendants if
+    /// `include_subtree` is set.
+    pub fn entity_to_json(
+        &self,
+        entity: Entity,
+        include_subtree: bool,
+    ) -> Result<String, EcsError> {
+        if !self.is_alive(entity) {
+            return Err(EcsError::EntityNotFound(
+                entity,
+                FindEntityLocation::ComponentManager,
+            ));
+        }
+        serde_json::to_string(&self.entity_data(entity, include_subtree)?)
+            .map_err(|err| EcsError::Serialization(err.to_string()))
+    }
+
+    fn entity_data(&self, entity: Entity, include_subtree: bool) -> Result<EntityData, EcsError> {
+        let mut components = Vec::new();
+        for column in self.component_store.values() {
+            let (Some(serializer), Some(component)) = (
+                self.serializers.get(column.type_name()),
+                column.get_any(entity),
+            ) else {
+                continue;
+            };
+            components.push(ComponentRecord {
+                entity,
+                type_name: column.type_name().to_string(),
+                version: serializer.version,
+                data: (serializer.serialize)(component)?,
+                sequence: column.sequence(entity).unwrap_or_default(),
+            });
+        }
+        components.sort_by_key(|record| record.sequence);
+
+        let mut children = Vec::new();
+        if include_subtree {
+            for child in self.entites.get_children(entity)? {
+                children.push(self.entity_data(*child, true)?);
+            }
+        }
+        Ok(EntityData {
+            components,
+            children,
+        })
+    }
+
+    /// Spawns the entity stored by [`Self::entity_to_json`] with its subtree, under
+    /// `parent` if given, and returns it.
+    ///
+    /// Fails without spawning anything if `json` contains an unregistered component type.
+    pub fn spawn_from_json(
+        &mut self,
+        json: &str,
+        parent: Option<Entity>,
+    ) -> Result<Entity, EcsError> {
+        let data: EntityData =
+            serde_json::from_str(json).map_err(|err| EcsError::Serialization(err.to_string()))?;
+        if let Some(parent) = parent.filter(|parent| !self.is_alive(*parent)) {
+            return Err(EcsError::EntityNotFound(
+                parent,
+                FindEntityLocation::EntityTree,
+            ));
+        }
+        let decoded = self.decode_entity(data)?;
+        Ok(self.spawn_decoded(decoded, parent))
+    }
+
+    fn decode_entity(&self, data: EntityData) -> Result<DecodedEntity, EcsError> {
+        Ok(DecodedEntity {
+            components: data
+                .components
+                .into_iter()
+                .map(|record| self.decode_record(record))
+                .collect::<Result<_, _>>()?,
+            children: data
+                .children
+                .into_iter()
+                .map(|child| self.decode_entity(child))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn spawn_decoded(&mut self, decoded: DecodedEntity, parent: Option<Entity>) -> Entity {
+        let entity = match parent {
+            Some(parent) => self
+                .create_child_entity(parent)
+                .expect("parent was checked to be alive"),
+            None => self.create_entity(),
+        };
+        for component in decoded.components {
+            self.store_insert(entity, component);
+        }
+        for child in decoded.children {
+            self.spawn_decoded(child, Some(entity));
+        }
+        entity
+    }
+
+    /// Returns the `T` components inserted or mutably accessed since the last
+    /// [`Self::clear_changed`].
+    pub fn query_changed<T: 'static>(&self) -> Vec<(Entity, &T)> {
+        self.column::<T>()
+            .map(|column| {
+                column
+                    .iter()
+                    .filter(|(entity, _)| column.is_changed(*entity) && self.is_queryable(*entity))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Resets the changed flag of every component, usually at the end of a frame.
+    pub fn clear_changed(&mut self) {
+        for column in self.component_store.values_mut() {
+            column.clear_changed();
+        }
+    }
+
+    /// Creates the entity `id`, e.g. to mirror an id assigned by a server.
+    ///
+    /// Later calls to [`Self::create_entity`] continue after `id` if it is ahead of
+    /// the counter. `Entity::none()` and ids from [`Self::reserve_entity`] that are
+    /// not flushed yet always count as taken.
+    pub fn spawn_with_id(&mut self, id: Entity) -> Result<Entity, EcsError> {
+        if id.is_none() || self.is_alive(id) || self.reserved.contains(&id) {
+            return Err(EcsError::EntityAlreadyExists(id));
+        }
+        self.insert_entity_node(id);
+        if id > self.entity_counter {
+            self.entity_counter = id;
+        }
+        Ok(id)
+    }
+
+    /// Marks `entity` for deletion by the next [`Self::delete_marked`].
+    ///
+    /// Marked entities are skipped by queries but stay alive until then.
+    pub fn mark_for_deletion(&mut self, entity: Entity) {
+        self.entites.mark_for_deletion(entity);
+    }
+
+    /// Deletes every entity marked with [`Self::mark_for_deletion`] in one pass.
+    ///
+    /// This is the manager side of [`EntityTree::compact`], [`Self::compact`] renumbers ids.
+    pub fn delete_marked(&mut self) {
+        let removed: HashSet<Entity> = self.entites.compact().into_iter().collect();
+        self.store_remove_where(|entity, _| removed.contains(&entity));
+        for entity in removed {
+            self.forget_entity(entity);
+            self.fire_despawn_hooks(entity);
+        }
+    }
+
+    /// Applies `f` to every `T` component, in parallel when the `rayon` feature is enabled.
+    ///
+    /// Without the feature the components are processed sequentially.
+    pub fn par_query_component_mut<T, F>(&mut self, f: F)
+    where
+        T: Send + 'static,
+        F: Fn(Entity, &mut T) + Sync,
+    {
+        let disabled = &self.disabled;
+        let tree = &self.entites;
+        let Some(column) = self
+            .component_store
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|column| typed_mut::<T>(column.as_mut()))
+        else {
+            return;
+        };
+        let components =
+            column.iter_mut_where(|entity| !disabled.contains(&entity) && !tree.is_marked(entity));
+
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+            components
+                .into_par_iter()
+                .for_each(|(entity, component)| f(entity, component));
+        }
+        #[cfg(not(feature = "rayon"))]
+        components
+            .into_iter()
+            .for_each(|(entity, component)| f(entity, component));
+    }
+
+    /// Returns the `T` components of all entities for which `pred` holds.
+    pub fn query_component_where_entity<T: 'static, P: Fn(Entity) -> bool>(
+        &self,
+        pred: P,
+    ) -> Vec<(Entity, &T)> {
+        self.column::<T>()
+            .map(|column| {
+                column
+                    .iter()
+                    .filter(|(entity, _)| self.is_queryable(*entity) && pred(*entity))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Registers a callback fired whenever a `T` component is inserted, including overwrites.
+    pub fn on_insert<T: 'static>(&mut self, cb: impl Fn(Entity) + 'static) {
+        self.insert_hooks
+            .entry(TypeId::of::<T>())
+            .or_default()
+            .push(Box::new(cb));
+    }
+
+    /// Registers a callback fired whenever a `T` component is removed, including
+    /// removals caused by deleting its entity.
+    pub fn on_remove<T: 'static>(&mut self, cb: impl Fn(Entity) + 'static) {
+        self.remove_hooks
+            .entry(TypeId::of::<T>())
+            .or_default()
+            .push(Box::new(cb));
+    }
+
+    /// Folds `f` over the `T` of `entity` and then the `T` of each ancestor up to the top.
+    ///
+    /// Entities on the chain without a `T` are skipped.
+    pub fn fold_ancestors<T: 'static, A, F: Fn(A, &T) -> A>(
+        &self,
+        entity: Entity,
+        init: A,
+        f: F,
+    ) -> Result<A, EcsError> {
+        if !self.is_alive(entity) {
+            return Err(EcsError::EntityNotFound(
+                entity,
+                FindEntityLocation::EntityTree,
+            ));
+        }
+        Ok(std::iter::once(entity)
+            .chain(self.entites.ancestors(entity))
+            .filter_map(|e| self.get_component::<T>(e))
+            .fold(init, f))
+    }
+
+    /// Returns an entity whose `T` satisfies `pred`.
+    ///
+    /// Components are scanned in unspecified order, so with several matches any of
+    /// them may be returned.
+    pub fn find_entity<T: 'static, F: Fn(&T) -> bool>(&self, pred: F) -> Option<Entity> {
+        self.queue_component::<T>()
+            .into_iter()
+            .find(|(_, component)| pred(component))
+            .map(|(entity, _)| entity)
+    }
+
+    /// Returns all entities whose `T` satisfies `pred`, in unspecified order.
+    pub fn find_all<T: 'static, F: Fn(&T) -> bool>(&self, pred: F) -> Vec<Entity> {
+        self.queue_component::<T>()
+            .into_iter()
+            .filter(|(_, component)| pred(component))
+            .map(|(entity, _)| entity)
+            .collect()
+    }
+
+    /// Joins all entities with both `W` and `R`, yielding `W` mutably and `R` shared.
+    ///
+    /// Returns nothing if `W` and `R` are the same type, as that would alias.
+    pub fn query_mut_read<W: 'static, R: 'static>(&mut self) -> Vec<(Entity, &mut W, &R)> {
+        let disabled = &self.disabled;
+        let tree = &self.entites;
+        let Some((writes, reads)) = split_columns::<W, R>(&mut self.component_store) else {
+            return Vec::new();
+        };
+
+        writes
+            .iter_mut_where(|entity| {
+                !disabled.contains(&entity) && !tree.is_marked(entity) && reads.contains(entity)
+            })
+            .into_iter()
+            .filter_map(|(entity, write)| Some((entity, write, reads.get(entity)?)))
+            .collect()
+    }
+
+    /// Every entity with both `A` and `B`, yielding `A` mutably and `B` shared.
+    ///
+    /// `A` and `B` live in separate columns, so no unsafe code is needed to borrow
+    /// both. See [`Self::query_mut_read`], which this forwards to.
+    pub fn query2_mut<A: 'static, B: 'static>(&mut self) -> Vec<(Entity, &mut A, &B)> {
+        self.query_mut_read::<A, B>()
+    }
+
+    /// Runs `f` on the `A` and `B` components of `entity`, `None` if either is missing.
+    ///
+    /// `A` is marked changed. Returns `None` if `A` and `B` are the same type.
+    pub fn with_components2<A: 'static, B: 'static, R>(
+        &mut self,
+        entity: Entity,
+        f: impl FnOnce(&mut A, &B) -> R,
+    ) -> Option<R> {
+        let (a, b) = split_columns::<A, B>(&mut self.component_store)?;
+        let b = b.get(entity)?;
+        Some(f(a.get_mut(entity)?, b))
+    }
+
+    /// Inserts a clone of `component` on each of `entities`.
+    pub fn insert_component_many<T: Clone + 'static>(&mut self, entities: &[Entity], component: T) {
+        for entity in entities {
+            self.store_insert_typed(*entity, component.clone());
+        }
+    }
+
+    /// Removes the `T` component from each of `entities`.
+    pub fn remove_component_from<T: 'static>(&mut self, entities: &[Entity]) {
+        for entity in entities {
+            self.store_remove_typed::<T>(*entity);
+        }
+    }
+
+    /// Inserts every component of `bundle` on `entity`.
+    pub fn insert_bundle<B: Bundle>(&mut self, entity: Entity, bundle: B) {
+        bundle.insert(self, entity);
+    }
+
+    /// Removes every component type of `B` from `entity` and returns them.
+    pub fn remove_bundle<B: Bundle>(&mut self, entity: Entity) -> B::Removed {
+        B::remove(self, entity)
+    }
+
+    /// Moves `T` into a [`DenseStore`], existing `T` components are moved along.
+    ///
+    /// Every component operation keeps working the same, only the layout changes.
+    pub fn register_dense<T: 'static>(&mut self) {
+        if self.dense_store::<T>().is_none() {
+            self.configure_component(ComponentConfig::<T>::dense());
+        }
+    }
+
+    /// Switches `T` to the storage of `config`, existing `T` components are moved along.
+    pub fn configure_component<T: 'static>(&mut self, config: ComponentConfig<T>) {
+        self.column_or_default::<T>().set_storage(config.storage);
+    }
+
+    /// The dense store of `T` if it was registered with [`Self::register_dense`].
+    pub fn dense_store<T: 'static>(&self) -> Option<&DenseStore<T>> {
+        self.column::<T>()?.dense()
+    }
+
+    /// Registers a check run by [`Self::try_insert_component`], replacing any previous one for `T`.
+    pub fn register_validator<T: 'static>(
+        &mut self,
+        validator: impl Fn(&T) -> Result<(), String> + 'static,
+    ) {
+        self.validators.insert(
+            TypeId::of::<T>(),
+            Box::new(move |component| match component.downcast_ref::<T>() {
+                Some(component) => validator(component),
+                None => Ok(()),
+            }),
+        );
+    }
+
+    /// Like [`Self::insert_component`], but rejects `Entity::none()` and components
+    /// failing the validator of `T`.
+    pub fn try_insert_component<T: 'static>(
+        &mut self,
+        entity: Entity,
+        component: T,
+    ) -> Result<(), EcsError> {
+        if !self.can_own_components(entity) {
+            return Err(EcsError::EntityNotFound(
+                entity,
+                FindEntityLocation::ComponentManager,
+            ));
+        }
+        if let Some(validator) = self.validators.get(&TypeId::of::<T>()) {
+            validator(&component).map_err(EcsError::ValidationFailed)?;
+        }
+        self.insert_component(entity, component);
+        Ok(())
+    }
+
+    /// Applies `f` to every `T` of a queryable entity, marks them changed and returns how
+    /// many there were.
+    ///
+    /// Like queries, this skips disabled entities and entities marked for deletion.
+    pub fn map_component<T: 'static, F: FnMut(&mut T)>(&mut self, mut f: F) -> usize {
+        let disabled = &self.disabled;
+        let tree = &self.entites;
+        let Some(column) = self
+            .component_store
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|column| typed_mut::<T>(column.as_mut()))
+        else {
+            return 0;
+        };
+        let components =
+            column.iter_mut_where(|entity| !disabled.contains(&entity) && !tree.is_marked(entity));
+        let count = components.len();
+        for (_, component) in components {
+            f(component);
+        }
+        count
+    }
+
+    /// Moves every entity of `other` into this manager under fresh ids.
+    ///
+    /// The root of `other` is attached under `parent`, other top-level entities stay
+    /// top-level. All components move along, not only registered or cloneable ones,
+    /// together with names and the disabled state. Component types new to this
+    /// manager keep the storage `other` configured for them. Returns the new
+    /// entities, each parent before its children.
+    pub fn merge_under(
+        &mut self,
+        mut other: EntityComponentManager,
+        parent: Entity,
+    ) -> Result<Vec<Entity>, EcsError> {
+        if !self.is_alive(parent) {
+            return Err(EcsError::EntityNotFound(
+                parent,
+                FindEntityLocation::EntityTree,
+            ));
+        }
+
+        let other_root = other.entites.root;
+        let order: Vec<Entity> = other.iter_hierarchy().collect();
+
+        let mut mapping = HashMap::new();
+        for old in &order {
+            let new = self.allocate_entity();
+            self.entites.insert_node(new);
+            match other.entites.parent.get(old).copied().flatten() {
+                Some(old_parent) => self.entites.add_child(mapping[&old_parent], new)?,
+                None if Some(*old) == other_root => self.entites.add_child(parent, new)?,
+                None => {}
+            }
+            mapping.insert(*old, new);
+        }
+
+        for (type_id, mut column) in std::mem::take(&mut other.component_store) {
+            let components: Vec<_> = column
+                .entities()
+                .into_iter()
+                .filter_map(|entity| Some((entity, column.remove_boxed(entity)?)))
+                .collect();
+            // The drained column still holds the storage `other` picked for the type.
+            column.clear();
+            self.component_store.entry(type_id).or_insert(column);
+            for (entity, component) in components {
+                if let Some(&new) = mapping.get(&entity) {
+                    self.store_insert(new, component);
+                }
+            }
+        }
+        for (entity, name) in std::mem::take(&mut other.names) {
+            if let Some(&new) = mapping.get(&entity) {
+                self.set_name(new, name);
+            }
+        }
+        self.disabled.extend(
+            other
+                .disabled
+                .iter()
+                .filter_map(|entity| mapping.get(entity)),
+        );
+        for (type_id, tagged) in &other.tags {
+            self.tags
+                .entry(*type_id)
+                .or_default()
+                .extend(tagged.iter().filter_map(|entity| mapping.get(entity)));
+        }
+
+        Ok(order.iter().map(|old| mapping[old]).collect())
+    }
+
+    /// Resolves a `/` separated path of entity names starting at the root, like `"root/child"`.
+    pub fn resolve_path(&self, path: &str) -> Option<Entity> {
+        let mut segments = path.split('/');
+        let first = segments.next();
+        let mut current = self.entites.root.filter(|root| self.name(*root) == first)?;
+        for segment in segments {
+            current = self
+                .entites
+                .get_children(current)
+                .ok()?
+                .iter()
+                .copied()
+                .find(|child| self.name(*child) == Some(segment))?;
+        }
+        Some(current)
+    }
+
+    /// Renumbers all entities to the dense range right after the id offset, `1..=n` by
+    /// default, and returns the old to new mapping.
+    ///
+    /// Live entities keep their relative order, reserved entities are numbered after them.
+    pub fn compact(&mut self) -> HashMap<Entity, Entity> {
+        let old: Vec<Entity> = self
+            .entites
+            .parent
+            .keys()
+            .chain(&self.reserved)
+            .copied()
+            .collect();
+        let mapping: HashMap<Entity, Entity> = old
+            .iter()
+            .zip(self.id_offset + 1..)
+            .map(|(old, new)| (*old, Entity(new)))
+            .collect();
+        let map = |entity: Entity| mapping.get(&entity).copied().unwrap_or(entity);
+
+        self.entites.remap(&mapping);
+        for column in self.component_store.values_mut() {
+            column.remap(&mapping);
+        }
+        self.blooms = std::mem::take(&mut self.blooms)
+            .into_iter()
+            .map(|(entity, bloom)| (map(entity), bloom))
+            .collect();
+        self.masks = std::mem::take(&mut self.masks)
+            .into_iter()
+            .map(|(entity, mask)| (map(entity), mask))
+            .collect();
+        self.names = std::mem::take(&mut self.names)
+            .into_iter()
+            .map(|(entity, name)| (map(entity), name))
+            .collect();
+        for entity in self.entities_by_name.values_mut() {
+            *entity = map(*entity);
+        }
+        self.disabled = self.disabled.iter().copied().map(map).collect();
+        for tagged in self.tags.values_mut() {
+            *tagged = tagged.iter().copied().map(map).collect();
+        }
+        for entity in &mut self.reserved {
+            *entity = map(*entity);
+        }
+        self.entity_counter = Entity(self.id_offset + old.len() as u64);
+        mapping
+    }
+
+    /// Borrows the manager for repeated access to the `T` components.
+    pub fn view<T: 'static>(&mut self) -> ComponentView<'_, T> {
+        ComponentView::new(self)
+    }
+
+    /// Deletes `entity` after moving its children to its parent, or to the root if it has none.
+    ///
+    /// The children take the place of `entity` among its siblings and keep their
+    /// subtrees and components. Children of a deleted root become top-level.
+    pub fn delete_entity_promote_children(&mut self, entity: Entity) -> Result<(), EcsError> {
+        if !self.is_alive(entity) {
+            return Err(EcsError::EntityNotFound(
+                entity,
+                FindEntityLocation::EntityTree,
+            ));
+        }
+        let new_parent = self
+            .entites
+            .get_parent(entity)
+            .ok()
+            .copied()
+            .or(self.entites.root)
+            .filter(|new_parent| *new_parent != entity);
+
+        if let Some(new_parent) = new_parent {
+            let position = self.entites.children[&new_parent]
+                .iter()
+                .position(|sibling| *sibling == entity)
+                .unwrap_or(usize::MAX);
+            let children = self.entites.children[&entity].clone();
+            for (offset, child) in children.into_iter().enumerate() {
+                self.entites
+                    .reparent_at(child, new_parent, position.saturating_add(offset))?;
+            }
+        }
+        self.delete_entity(entity);
+        Ok(())
+    }
+
+    /// All entities in tree order, every parent before its children.
+    ///
+    /// The root's subtree comes first, followed by the subtrees of detached
+    /// top-level entities in id order.
+    pub fn iter_hierarchy(&self) -> impl Iterator<Item = Entity> + '_ {
+        let root = self.entites.root;
+        let detached = self
+            .entites
+            .parent
+            .iter()
+            .filter(move |(entity, parent)| parent.is_none() && Some(**entity) != root)
+            .map(|(entity, _)| *entity);
+        root.into_iter()
+            .chain(detached)
+            .flat_map(|top| self.entites.iter_from(top))
+    }
+
+    /// Entities and component types that differ from `self` to `other`.
+    pub fn diff(&self, other: &EntityComponentManager) -> WorldDiff {
+        let mut diff = WorldDiff::default();
+        // The tree maps are ordered, so all lists come out sorted by id.
+        for entity in other.entites.children.keys() {
+            if !self.is_alive(*entity) {
+                diff.added_entities.push(*entity);
+            }
+        }
+        for entity in self.entites.children.keys().copied() {
+            if !other.is_alive(entity) {
+                diff.removed_entities.push(entity);
+                continue;
+            }
+            let types = |manager: &EntityComponentManager| -> BTreeSet<&'static str> {
+                manager
+                    .components_of(entity)
+                    .into_iter()
+                    .map(|(type_name, _)| type_name)
+                    .collect()
+            };
+            let (before, after) = (types(self), types(other));
+            let changes = ComponentChanges {
+                added: after.difference(&before).copied().collect(),
+                removed: before.difference(&after).copied().collect(),
+            };
+            if !changes.added.is_empty() || !changes.removed.is_empty() {
+                diff.changed_components.insert(entity, changes);
+            }
+        }
+        diff
+    }
+
+    /// Human readable listing of every entity with its parent and component type names.
+    ///
+    /// Entities are indented by their depth in the hierarchy.
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        for entity in self.iter_hierarchy() {
+            let depth = self.entites.ancestors(entity).count();
+            let mut types: Vec<_> = self
+                .components_of(entity)
+                .into_iter()
+                .map(|(type_name, _)| type_name)
+                .collect();
+            types.sort_unstable();
+
+            out.push_str(&"  ".repeat(depth));
+            out.push_str(&entity.to_string());
+            if let Some(name) = self.name(entity) {
+                out.push_str(&format!(" \"{}\"", name));
+            }
+            if let Ok(parent) = self.entites.get_parent(entity) {
+                out.push_str(&format!(" parent={}", parent));
+            }
+            out.push_str(&format!(" [{}]\n", types.join(", ")));
+        }
+        out
+    }
+
+    /// Computes a `W` component for every entity with a local `L` component, parents first.
+    ///
+    /// `combine` receives the `W` just computed for the parent, or `None` if the parent
+    /// has no `L`, and the entity's own `L`. Entities without `L` are left untouched.
+    pub fn propagate<L, W, F>(&mut self, combine: F)
+    where
+        L: 'static,
+        W: Clone + 'static,
+        F: Fn(Option<&W>, &L) -> W,
+    {
+        let order: Vec<Entity> = self.iter_hierarchy().collect();
+        let mut computed: HashMap<Entity, W> = HashMap::new();
+        for entity in order {
+            let Some(local) = self.get_component::<L>(entity) else {
+                continue;
+            };
+            let parent = self
+                .entites
+                .get_parent(entity)
+                .ok()
+                .and_then(|parent| computed.get(parent));
+            let world = combine(parent, local);
+            computed.insert(entity, world.clone());
+            self.insert_component(entity, world);
+        }
+    }
+
+    /// Entities in the tree that carry no components or tags, in id order.
+    pub fn empty_entities(&self) -> Vec<Entity> {
+        let owners: HashSet<Entity> = self
+            .component_store
+            .values()
+            .flat_map(|column| column.entities())
+            .chain(self.tags.values().flatten().copied())
+            .collect();
+        self.entites
+            .parent
+            .keys()
+            .copied()
+            .filter(|entity| !owners.contains(entity))
+            .collect()
+    }
+
+    /// Deletes every entity from [`Self::empty_entities`] except the root.
+    pub fn prune_empty(&mut self) {
+        let root = self.entites.root;
+        for entity in self.empty_entities() {
+            if Some(entity) != root {
+                self.delete_entity(entity);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stored_components(manager: &EntityComponentManager) -> usize {
+        manager
+            .component_store
+            .values()
+            .map(|column| column.len())
+            .sum()
+    }
+
+    #[test]
+    fn create_entity() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        assert_eq!(entity, Entity(1));
+    }
+
+    #[test]
+    fn delete_entity() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.delete_entity(entity);
+        assert_eq!(manager.entites.len(), 0);
+    }
+
+    #[test]
+    fn insert_component() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 1);
+        assert_eq!(manager.get_component::<i32>(entity), Some(&1));
+    }
+
+    #[test]
+    fn get_component() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 1);
+        assert_eq!(manager.get_component::<i32>(entity), Some(&1));
+    }
+
+    #[test]
+    fn get_component_mut() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 1);
+        assert_eq!(manager.get_component_mut::<i32>(entity), Some(&mut 1));
+    }
+
+    #[test]
+    fn remove_component() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 1);
+        manager.remove_component::<i32>(entity);
+        assert_eq!(manager.get_component::<i32>(entity), None);
+    }
+
+    #[test]
+    fn get_component_with_different_type() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 1);
+        assert_eq!(manager.get_component::<f32>(entity), None);
+    }
+
+    #[test]
+    fn get_component_mut_with_different_type() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 1);
+        assert_eq!(manager.get_component_mut::<f32>(entity), None);
+    }
+
+    #[test]
+    fn remove_component_with_different_type() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 1);
+        manager.remove_component::<f32>(entity);
+        assert_eq!(manager.get_component::<i32>(entity), Some(&1));
+    }
+
+    #[test]
+    fn get_component_with_different_entity() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 1);
+        assert_eq!(manager.get_component::<i32>(Entity(2)), None);
+    }
+
+    #[test]
+    fn get_component_mut_with_different_entity() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 1);
+        assert_eq!(manager.get_component_mut::<i32>(Entity(2)), None);
+    }
+
+    #[test]
+    fn remove_component_with_different_entity() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 1);
+        manager.remove_component::<i32>(Entity(2));
+        assert_eq!(manager.get_component::<i32>(entity), Some(&1));
+    }
+
+    #[test]
+    fn remove_entity_with_component() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 1);
+        manager.delete_entity(entity);
+        assert_eq!(manager.get_component::<i32>(entity), None);
+        assert_eq!(stored_components(&manager), 0);
+    }
+
+    #[test]
+    fn add_struct_component() {
+        #[derive(Debug, PartialEq)]
+        struct Component {
+            value: i32,
+        }
+
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, Component { value: 1 });
+        assert_eq!(
+            manager.get_component::<Component>(entity),
+            Some(&Component { value: 1 })
+        );
+    }
+
+    #[test]
+    fn clone_entity() {
+        let mut manager = EntityComponentManager::default();
+        manager.register_cloneable::<i32>();
+        manager.register_cloneable::<String>();
+
+        let source = manager.create_entity();
+        manager.insert_component(source, 1);
+        manager.insert_component(source, String::from("source"));
+
+        let copy = manager.clone_entity(source).unwrap();
+        assert_ne!(copy, source);
+        assert_eq!(manager.get_component::<i32>(copy), Some(&1));
+        assert_eq!(
+            manager.get_component::<String>(copy),
+            Some(&String::from("source"))
+        );
+
+        *manager.get_component_mut::<i32>(copy).unwrap() = 2;
+        manager
+            .get_component_mut::<String>(copy)
+            .unwrap()
+            .push_str("-copy");
+        assert_eq!(manager.get_component::<i32>(source), Some(&1));
+        assert_eq!(
+            manager.get_component::<String>(source),
+            Some(&String::from("source"))
+        );
+    }
+
+    #[test]
+    fn clone_entity_skips_unregistered() {
+        let mut manager = EntityComponentManager::default();
+        manager.register_cloneable::<i32>();
+
+        let source = manager.create_entity();
+        manager.insert_component(source, 1);
+        manager.insert_component(source, 1.0f32);
+
+        let copy = manager.clone_entity(source).unwrap();
+        assert_eq!(manager.get_component::<i32>(copy), Some(&1));
+        assert_eq!(manager.get_component::<f32>(copy), None);
+        assert_eq!(manager.clone_entity(Entity(42)), None);
+    }
+
+    #[test]
+    fn query_named() {
+        let mut manager = EntityComponentManager::default();
+        let named = manager.create_entity();
+        let unnamed = manager.create_entity();
+        manager.insert_component(named, 1);
+        manager.insert_component(unnamed, 2);
+        manager.set_name(named, "player");
+
+        let mut result = manager.query_named::<i32>();
+        result.sort_by_key(|(entity, _, _)| *entity);
+        assert_eq!(
+            result,
+            vec![(named, Some("player"), &1), (unnamed, None, &2)]
+        );
+    }
+
+    #[test]
+    fn take_component() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 1);
+        assert_eq!(manager.take_component::<i32>(entity), Some(1));
+        assert_eq!(manager.take_component::<i32>(entity), None);
+    }
+
+    #[test]
+    fn swap_component_both_present() {
+        let mut manager = EntityComponentManager::default();
+        let a = manager.create_entity();
+        let b = manager.create_entity();
+        manager.insert_component(a, 1);
+        manager.insert_component(b, 2);
+        manager.swap_component::<i32>(a, b);
+        assert_eq!(manager.get_component::<i32>(a), Some(&2));
+        assert_eq!(manager.get_component::<i32>(b), Some(&1));
+    }
+
+    #[test]
+    fn swap_component_one_present() {
+        let mut manager = EntityComponentManager::default();
+        let a = manager.create_entity();
+        let b = manager.create_entity();
+        manager.insert_component(a, 1);
+        manager.swap_component::<i32>(a, b);
+        assert_eq!(manager.get_component::<i32>(a), None);
+        assert_eq!(manager.get_component::<i32>(b), Some(&1));
+    }
+
+    #[test]
+    fn swap_component_neither_present() {
+        let mut manager = EntityComponentManager::default();
+        let a = manager.create_entity();
+        let b = manager.create_entity();
+        manager.insert_component(a, 1.0f32);
+        manager.swap_component::<i32>(a, b);
+        assert_eq!(manager.get_component::<i32>(a), None);
+        assert_eq!(manager.get_component::<i32>(b), None);
+        assert_eq!(stored_components(&manager), 1);
+    }
+
+    #[test]
+    fn components_of() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        let other = manager.create_entity();
+        manager.insert_component(entity, 1);
+        manager.insert_component(entity, 1.0f32);
+        manager.insert_component(entity, String::from("name"));
+        manager.insert_component(other, 2u8);
+
+        let components = manager.components_of(entity);
+        let mut names: Vec<_> = components.iter().map(|(name, _)| *name).collect();
+        names.sort();
+        let mut expected = vec![
+            std::any::type_name::<i32>(),
+            std::any::type_name::<f32>(),
+            std::any::type_name::<String>(),
+        ];
+        expected.sort();
+        assert_eq!(names, expected);
+
+        let value = components
+            .iter()
+            .find_map(|(_, component)| component.downcast_ref::<i32>());
+        assert_eq!(value, Some(&1));
+    }
+
+    #[test]
+    fn extend_components() {
+        let mut manager = EntityComponentManager::default();
+        let entities: Vec<_> = (0..3).map(|_| manager.create_entity()).collect();
+        manager.extend_components(vec![(entities[0], 1), (entities[1], 2), (entities[2], 3)]);
+
+        assert_eq!(manager.get_component::<i32>(entities[0]), Some(&1));
+        assert_eq!(manager.get_component::<i32>(entities[1]), Some(&2));
+        assert_eq!(manager.get_component::<i32>(entities[2]), Some(&3));
+    }
+
+    #[test]
+    fn clear_components() {
+        let mut manager = EntityComponentManager::default();
+        for value in 0..3 {
+            let entity = manager.create_entity();
+            manager.insert_component(entity, value);
+            manager.insert_component(entity, value as f32);
+        }
+        assert_eq!(manager.count_components::<i32>(), 3);
+
+        manager.clear_components::<i32>();
+        assert_eq!(manager.count_components::<i32>(), 0);
+        assert_eq!(manager.count_components::<f32>(), 3);
+    }
+
+    #[test]
+    fn for_each_component_of() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 1);
+        manager.insert_component(entity, 2.0f32);
+
+        let mut visited = Vec::new();
+        manager.for_each_component_of(entity, |type_name, component| {
+            visited.push(type_name.to_string());
+            if let Some(value) = component.downcast_ref::<i32>() {
+                assert_eq!(*value, 1);
+            }
+        });
+        visited.sort();
+        assert_eq!(visited, vec!["f32", "i32"]);
+    }
+
+    #[test]
+    fn set_enabled() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 1);
+
+        manager.set_enabled(entity, false);
+        assert!(!manager.is_enabled(entity));
+        assert!(manager.queue_component::<i32>().is_empty());
+        assert_eq!(manager.get_component::<i32>(entity), Some(&1));
+
+        manager.set_enabled(entity, true);
+        assert_eq!(manager.queue_component::<i32>(), vec![(entity, &1)]);
+    }
+
+    #[test]
+    fn set_enabled_recursive() {
+        let mut manager = EntityComponentManager::default();
+        let parent = manager.create_entity();
+        let child = manager.create_entity();
+        let other = manager.create_entity();
+        manager.entites.add_child(parent, child).unwrap();
+        manager.insert_component(parent, 1);
+        manager.insert_component(child, 2);
+        manager.insert_component(other, 3);
+
+        manager.set_enabled_recursive(parent, false);
+        assert_eq!(manager.queue_component::<i32>(), vec![(other, &3)]);
+
+        manager.set_enabled_recursive(parent, true);
+        assert_eq!(manager.queue_component::<i32>().len(), 3);
+    }
+
+    #[test]
+    fn find_two() {
+        let mut manager = EntityComponentManager::default();
+        let first = manager.create_entity();
+        let second = manager.create_entity();
+        manager.insert_component(first, 1);
+        manager.insert_component(first, 1.0f32);
+        manager.insert_component(second, 2);
+        manager.insert_component(second, 5.0f32);
+
+        assert_eq!(
+            manager.find_two::<i32, f32, _>(|a, b| *a == 2 && *b > 4.0),
+            Some((second, &2, &5.0))
+        );
+        assert_eq!(manager.find_two::<i32, f32, _>(|a, _| *a > 2), None);
+    }
+
+    #[test]
+    fn find_two_short_circuits() {
+        let mut manager = EntityComponentManager::default();
+        for value in 0..10 {
+            let entity = manager.create_entity();
+            manager.insert_component(entity, value);
+            manager.insert_component(entity, value as f32);
+        }
+
+        let calls = std::cell::Cell::new(0);
+        let found = manager.find_two::<i32, f32, _>(|_, _| {
+            calls.set(calls.get() + 1);
+            true
+        });
+        assert!(found.is_some());
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn despawn_all_order() {
+        use std::{cell::RefCell, rc::Rc};
+
+        for (order, expected) in [
+            (
+                DespawnOrder::SpawnOrder,
+                vec![Entity(1), Entity(2), Entity(3)],
+            ),
+            (
+                DespawnOrder::ReverseSpawnOrder,
+                vec![Entity(3), Entity(2), Entity(1)],
+            ),
+        ] {
+            let mut manager = EntityComponentManager::default();
+            let despawned = Rc::new(RefCell::new(Vec::new()));
+            let log = despawned.clone();
+            manager.on_despawn(move |entity| log.borrow_mut().push(entity));
+
+            for _ in 0..3 {
+                let entity = manager.create_entity();
+                manager.insert_component(entity, 1);
+            }
+            manager.despawn_all(order);
+
+            assert_eq!(*despawned.borrow(), expected);
+            assert_eq!(manager.entites.len(), 0);
+            assert_eq!(stored_components(&manager), 0);
+        }
+    }
+
+    #[test]
+    fn query_with_and_without() {
+        struct Position;
+        struct Frozen;
+        struct Sprite;
+
+        let mut manager = EntityComponentManager::default();
+        let matching = manager.create_entity();
+        let frozen = manager.create_entity();
+        let without_sprite = manager.create_entity();
+        manager.insert_component(matching, Position);
+        manager.insert_component(matching, Sprite);
+        manager.insert_component(frozen, Position);
+        manager.insert_component(frozen, Frozen);
+        manager.insert_component(frozen, Sprite);
+        manager.insert_component(without_sprite, Position);
+
+        let mut with_sprite: Vec<_> = manager
+            .query_with::<Position, Sprite>()
+            .into_iter()
+            .map(|(entity, _)| entity)
+            .collect();
+        with_sprite.sort();
+        assert_eq!(with_sprite, vec![matching, frozen]);
+
+        let mut not_frozen: Vec<_> = manager
+            .query_without::<Position, Frozen>()
+            .into_iter()
+            .map(|(entity, _)| entity)
+            .collect();
+        not_frozen.sort();
+        assert_eq!(not_frozen, vec![matching, without_sprite]);
+
+        assert!(manager.has_component::<Frozen>(frozen));
+        assert!(!manager.has_component::<Frozen>(matching));
+    }
+
+    #[test]
+    fn is_alive() {
+        let mut manager = EntityComponentManager::default();
+        let alive = manager.create_entity();
+        let deleted = manager.create_entity();
+        manager.delete_entity(deleted);
+
+        assert!(manager.is_alive(alive));
+        assert!(!manager.is_alive(deleted));
+        assert!(!manager.is_alive(Entity::none()));
+        assert!(!manager.is_alive(Entity(42)));
+    }
+
+    #[test]
+    fn component_count_cache() {
+        fn scan<T: 'static>(manager: &EntityComponentManager) -> usize {
+            manager.queue_component::<T>().len()
+        }
+
+        let mut manager = EntityComponentManager::default();
+        manager.register_cloneable::<i32>();
+        assert!(!manager.any_with_component::<i32>());
+
+        let entities: Vec<_> = (0..4).map(|_| manager.create_entity()).collect();
+        for (value, entity) in entities.iter().enumerate() {
+            manager.insert_component(*entity, value as i32);
+            manager.insert_component(*entity, value as f32);
+        }
+        manager.insert_component(entities[0], 10);
+        assert_eq!(manager.count_components::<i32>(), scan::<i32>(&manager));
+
+        manager.remove_component::<i32>(entities[1]);
+        manager.remove_component::<i32>(entities[1]);
+        assert_eq!(manager.count_components::<i32>(), scan::<i32>(&manager));
+
+        manager.delete_entity(entities[2]);
+        assert_eq!(manager.count_components::<i32>(), scan::<i32>(&manager));
+        assert_eq!(manager.count_components::<f32>(), scan::<f32>(&manager));
+
+        manager.clone_entity(entities[0]);
+        manager.take_component::<f32>(entities[3]);
+        assert_eq!(manager.count_components::<i32>(), scan::<i32>(&manager));
+        assert_eq!(manager.count_components::<f32>(), scan::<f32>(&manager));
+
+        manager.clear_components::<i32>();
+        assert_eq!(manager.count_components::<i32>(), 0);
+        assert!(!manager.any_with_component::<i32>());
+        assert!(manager.any_with_component::<f32>());
+    }
+
+    #[test]
+    fn first_entity_becomes_root() {
+        let mut manager = EntityComponentManager::default();
+        assert_eq!(manager.root(), None);
+
+        let first = manager.create_entity();
+        let second = manager.create_entity();
+        assert_eq!(manager.root(), Some(first));
+
+        manager.set_root(second);
+        manager.create_entity();
+        assert_eq!(manager.root(), Some(second));
+    }
+
+    #[test]
+    fn create_child_entity() {
+        let mut manager = EntityComponentManager::default();
+        let parent = manager.create_entity();
+        let child1 = manager.create_child_entity(parent).unwrap();
+        let child2 = manager.create_child_entity(parent).unwrap();
+
+        assert_eq!(manager.get_children(parent).unwrap(), &vec![child1, child2]);
+        assert_eq!(manager.get_parent(child1).unwrap(), &parent);
+        assert!(manager.get_parent(parent).is_err());
+        assert!(manager.create_child_entity(Entity(42)).is_err());
+        assert_eq!(manager.entites.len(), 3);
+    }
+
+    #[test]
+    fn delete_child_entity() {
+        let mut manager = EntityComponentManager::default();
+        let parent = manager.create_entity();
+        let child1 = manager.create_child_entity(parent).unwrap();
+        let child2 = manager.create_child_entity(parent).unwrap();
+
+        manager.delete_entity(child1);
+        assert_eq!(manager.get_children(parent).unwrap(), &vec![child2]);
+    }
+
+    #[test]
+    fn serialize_world_skips_non_serializable() {
+        use crate::component::Component;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Position(i32);
+        impl Component for Position {}
+        impl SerializableComponent for Position {}
+
+        struct GpuHandle;
+        impl Component for GpuHandle {}
+
+        let mut manager = EntityComponentManager::default();
+        manager.register_component::<Position>(1);
+        let parent = manager.create_entity();
+        let child = manager.create_child_entity(parent).unwrap();
+        manager.insert_component(parent, Position(1));
+        manager.insert_component(child, Position(2));
+        manager.insert_component(child, GpuHandle);
+        manager.set_name(child, "child");
+
+        let data = manager.serialize_world().unwrap();
+        assert!(!data.contains("GpuHandle"));
+
+        let mut loaded = EntityComponentManager::default();
+        loaded.register_component::<Position>(1);
+        loaded.deserialize_world(&data).unwrap();
+        assert_eq!(loaded.get_component::<Position>(parent), Some(&Position(1)));
+        assert_eq!(loaded.get_component::<Position>(child), Some(&Position(2)));
+        assert!(!loaded.has_component::<GpuHandle>(child));
+        assert_eq!(loaded.get_children(parent).unwrap(), &vec![child]);
+        assert_eq!(loaded.name(child), Some("child"));
+        assert_eq!(loaded.count_components::<Position>(), 2);
+        assert_eq!(loaded.create_entity(), Entity(3));
+
+        let mut unregistered = EntityComponentManager::default();
+        assert!(matches!(
+            unregistered.deserialize_world(&data),
+            Err(EcsError::Serialization(_))
+        ));
+    }
+
+    #[test]
+    fn query_changed() {
+        let mut manager = EntityComponentManager::default();
+        let first = manager.create_entity();
+        let second = manager.create_entity();
+        manager.insert_component(first, 1);
+        manager.insert_component(second, 2);
+        assert_eq!(manager.query_changed::<i32>().len(), 2);
+
+        manager.clear_changed();
+        assert!(manager.query_changed::<i32>().is_empty());
+
+        *manager.get_component_mut::<i32>(second).unwrap() = 3;
+        assert_eq!(manager.query_changed::<i32>(), vec![(second, &3)]);
+
+        manager.clear_changed();
+        manager.insert_component(first, 4);
+        assert_eq!(manager.query_changed::<i32>(), vec![(first, &4)]);
+    }
+
+    #[test]
+    fn spawn_with_id() {
+        let mut manager = EntityComponentManager::default();
+        let first = manager.create_entity();
+        let remote = manager.spawn_with_id(Entity(100)).unwrap();
+        assert_eq!(remote, Entity(100));
+        assert!(manager.is_alive(remote));
+
+        assert!(matches!(
+            manager.spawn_with_id(first),
+            Err(EcsError::EntityAlreadyExists(entity)) if entity == first
+        ));
+        assert!(manager.spawn_with_id(Entity::none()).is_err());
+
+        assert_eq!(manager.create_entity(), Entity(101));
+        assert_eq!(manager.spawn_with_id(Entity(50)).unwrap(), Entity(50));
+        assert_eq!(manager.create_entity(), Entity(102));
+    }
+
+    #[test]
+    fn delete_marked() {
+        let mut manager = EntityComponentManager::default();
+        let parent1 = manager.create_entity();
+        let parent2 = manager.create_entity();
+        let a = manager.create_child_entity(parent1).unwrap();
+        let b = manager.create_child_entity(parent1).unwrap();
+        let c = manager.create_child_entity(parent2).unwrap();
+        let kept = manager.create_child_entity(parent2).unwrap();
+        for entity in [a, b, c, kept] {
+            manager.insert_component(entity, 1);
+        }
+
+        for entity in [a, b, c] {
+            manager.mark_for_deletion(entity);
+        }
+        assert!(manager.is_alive(a));
+        assert_eq!(manager.queue_component::<i32>(), vec![(kept, &1)]);
+
+        manager.delete_marked();
+        for entity in [a, b, c] {
+            assert!(!manager.is_alive(entity));
+            assert!(!manager.has_component::<i32>(entity));
+        }
+        assert!(manager.get_children(parent1).unwrap().is_empty());
+        assert_eq!(manager.get_children(parent2).unwrap(), &vec![kept]);
+        assert_eq!(manager.count_components::<i32>(), 1);
+    }
+
+    #[test]
+    fn par_query_component_mut() {
+        use std::sync::Mutex;
+
+        let mut manager = EntityComponentManager::default();
+        let entities: Vec<_> = (0..100).map(|_| manager.create_entity()).collect();
+        for (value, entity) in entities.iter().enumerate() {
+            manager.insert_component(*entity, value as i32);
+        }
+        manager.insert_component(entities[0], 1.0f32);
+
+        let visited = Mutex::new(Vec::new());
+        manager.par_query_component_mut::<i32, _>(|entity, value| {
+            *value *= 2;
+            visited.lock().unwrap().push(entity);
+        });
+
+        let mut visited = visited.into_inner().unwrap();
+        visited.sort();
+        assert_eq!(visited, entities);
+        for (value, entity) in entities.iter().enumerate() {
+            assert_eq!(
+                manager.get_component::<i32>(*entity),
+                Some(&(value as i32 * 2))
+            );
+        }
+    }
+
+    #[test]
+    fn query_component_where_entity() {
+        let mut manager = EntityComponentManager::default();
+        for value in 0..6 {
+            let entity = manager.create_entity();
+            if value % 3 != 0 {
+                manager.insert_component(entity, value);
+            } else {
+                manager.insert_component(entity, value as f32);
+            }
+        }
+
+        let mut result = manager.query_component_where_entity::<i32, _>(|entity| entity.0 <= 3);
+        result.sort_by_key(|(entity, _)| *entity);
+        assert_eq!(result, vec![(Entity(2), &1), (Entity(3), &2)]);
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        use crate::component::Component;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Health(i32);
+        impl Component for Health {}
+        impl SerializableComponent for Health {}
+
+        let mut manager = EntityComponentManager::default();
+        manager.register_component::<Health>(1);
+        let player = manager.create_entity();
+        let enemy = manager.create_child_entity(player).unwrap();
+        manager.insert_component(player, Health(10));
+        manager.insert_component(enemy, Health(5));
+
+        let snapshot = manager.snapshot().unwrap();
+
+        manager.get_component_mut::<Health>(player).unwrap().0 = 1;
+        manager.delete_entity(enemy);
+        let spawned = manager.create_entity();
+        manager.insert_component(spawned, Health(3));
+
+        manager.restore(&snapshot).unwrap();
+        assert_eq!(manager.get_component::<Health>(player), Some(&Health(10)));
+        assert_eq!(manager.get_component::<Health>(enemy), Some(&Health(5)));
+        assert!(!manager.is_alive(spawned));
+        assert_eq!(manager.get_children(player).unwrap(), &vec![enemy]);
+        assert_eq!(manager.count_components::<Health>(), 2);
+        assert_eq!(manager.create_entity(), spawned);
+    }
+
+    #[test]
+    fn reserve_entity() {
+        let mut manager = EntityComponentManager::default();
+        let reserved: Vec<_> = (0..3).map(|_| manager.reserve_entity()).collect();
+        assert_eq!(reserved, vec![Entity(1), Entity(2), Entity(3)]);
+        assert!(!manager.is_alive(reserved[0]));
+        assert_eq!(manager.get_component::<i32>(reserved[0]), None);
+        assert_eq!(manager.create_entity(), Entity(4));
+
+        manager.flush_reserved();
+        for (value, entity) in reserved.iter().enumerate() {
+            assert!(manager.is_alive(*entity));
+            manager.insert_component(*entity, value as i32);
+        }
+        for (value, entity) in reserved.iter().enumerate() {
+            assert_eq!(manager.get_component::<i32>(*entity), Some(&(value as i32)));
+        }
+        assert_eq!(manager.root(), Some(Entity(4)));
+    }
+
+    #[test]
+    fn versioned_components() {
+        use crate::component::Component;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Health(i64);
+        impl Component for Health {}
+        impl SerializableComponent for Health {}
+
+        let mut v2 = EntityComponentManager::default();
+        v2.register_component::<Health>(2);
+        let entity = v2.create_entity();
+        v2.insert_component(entity, Health(5));
+        let data = v2.serialize_world().unwrap();
+        assert!(data.contains("\"version\":2"));
+
+        v2.deserialize_world(&data).unwrap();
+        assert_eq!(v2.get_component::<Health>(entity), Some(&Health(5)));
+
+        // Version 3 stores health in half points.
+        let mut v3 = EntityComponentManager::default();
+        v3.register_component::<Health>(3);
+        assert!(v3.deserialize_world(&data).is_err());
+
+        v3.register_migration::<Health>(2, |data| {
+            serde_json::Value::from(data.as_i64().unwrap() * 2)
+        })
+        .unwrap();
+        v3.deserialize_world(&data).unwrap();
+        assert_eq!(v3.get_component::<Health>(entity), Some(&Health(10)));
+
+        let mut v1 = EntityComponentManager::default();
+        v1.register_component::<Health>(1);
+        assert!(v1.deserialize_world(&data).is_err());
+    }
+
+    #[test]
+    fn component_observers() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let mut manager = EntityComponentManager::default();
+        let inserted = Rc::new(RefCell::new(Vec::new()));
+        let removed = Rc::new(RefCell::new(Vec::new()));
+        let log = inserted.clone();
+        manager.on_insert::<i32>(move |entity| log.borrow_mut().push(entity));
+        let log = removed.clone();
+        manager.on_remove::<i32>(move |entity| log.borrow_mut().push(entity));
+
+        let first = manager.create_entity();
+        let second = manager.create_entity();
+        manager.insert_component(first, 1);
+        manager.insert_component(second, 2);
+        manager.insert_component(second, 1.0f32);
+        assert_eq!(*inserted.borrow(), vec![first, second]);
+
+        manager.remove_component::<i32>(first);
+        manager.remove_component::<i32>(first);
+        manager.remove_component::<f32>(second);
+        assert_eq!(*removed.borrow(), vec![first]);
+
+        manager.delete_entity(second);
+        assert_eq!(*removed.borrow(), vec![first, second]);
+    }
+
+    #[test]
+    fn fold_ancestors() {
+        let mut manager = EntityComponentManager::default();
+        let root = manager.create_entity();
+        let child = manager.create_child_entity(root).unwrap();
+        let grandchild = manager.create_child_entity(child).unwrap();
+        manager.insert_component(root, 100);
+        manager.insert_component(child, 10);
+        manager.insert_component(grandchild, 1);
+
+        assert_eq!(
+            manager
+                .fold_ancestors::<i32, _, _>(grandchild, 0, |total, offset| total + offset)
+                .unwrap(),
+            111
+        );
+        assert_eq!(
+            manager
+                .fold_ancestors::<i32, _, _>(child, 0, |total, offset| total + offset)
+                .unwrap(),
+            110
+        );
+        assert!(manager
+            .fold_ancestors::<i32, _, _>(Entity(42), 0, |total, offset| total + offset)
+            .is_err());
+    }
+
+    #[test]
+    fn find_entity() {
+        let mut manager = EntityComponentManager::default();
+        let player = manager.create_entity();
+        let enemy = manager.create_entity();
+        let other = manager.create_entity();
+        manager.insert_component(player, String::from("player"));
+        manager.insert_component(enemy, String::from("enemy"));
+        manager.insert_component(other, String::from("enemy"));
+
+        assert_eq!(
+            manager.find_entity::<String, _>(|name| name == "player"),
+            Some(player)
+        );
+        assert_eq!(manager.find_entity::<String, _>(|name| name == "npc"), None);
+
+        let mut enemies = manager.find_all::<String, _>(|name| name == "enemy");
+        enemies.sort();
+        assert_eq!(enemies, vec![enemy, other]);
+        assert!(manager
+            .find_all::<String, _>(|name| name.is_empty())
+            .is_empty());
+    }
+
+    #[test]
+    fn query_mut_read() {
+        #[derive(Debug, PartialEq)]
+        struct Velocity(i32);
+        struct Mass(i32);
+
+        let mut manager = EntityComponentManager::default();
+        let heavy = manager.create_entity();
+        let light = manager.create_entity();
+        let massless = manager.create_entity();
+        manager.insert_component(heavy, Velocity(2));
+        manager.insert_component(heavy, Mass(10));
+        manager.insert_component(light, Velocity(3));
+        manager.insert_component(light, Mass(1));
+        manager.insert_component(massless, Velocity(4));
+
+        let joined = manager.query_mut_read::<Velocity, Mass>();
+        assert_eq!(joined.len(), 2);
+        for (_, velocity, mass) in joined {
+            velocity.0 *= mass.0;
+        }
+
+        assert_eq!(
+            manager.get_component::<Velocity>(heavy),
+            Some(&Velocity(20))
+        );
+        assert_eq!(manager.get_component::<Velocity>(light), Some(&Velocity(3)));
+        assert_eq!(
+            manager.get_component::<Velocity>(massless),
+            Some(&Velocity(4))
+        );
+        assert!(manager.query_mut_read::<Velocity, Velocity>().is_empty());
+    }
+
+    #[test]
+    fn remove_component_from() {
+        let mut manager = EntityComponentManager::default();
+        let entities: Vec<_> = (0..5).map(|_| manager.create_entity()).collect();
+        for entity in &entities {
+            manager.insert_component(*entity, 1);
+            manager.insert_component(*entity, 1.0f32);
+        }
+
+        manager.remove_component_from::<i32>(&entities[..3]);
+        assert_eq!(manager.count_components::<i32>(), 2);
+        assert_eq!(manager.count_components::<f32>(), 5);
+        assert!(manager.has_component::<i32>(entities[3]));
+        assert!(manager.has_component::<i32>(entities[4]));
+    }
+
+    #[test]
+    fn bloom_has_no_false_negatives() {
+        struct Marker<const N: usize>;
+
+        macro_rules! for_markers {
+            ($action:ident) => {
+                $action!(0);
+                $action!(1);
+                $action!(2);
+                $action!(3);
+                $action!(4);
+                $action!(5);
+                $action!(6);
+                $action!(7);
+                $action!(8);
+                $action!(9);
+                $action!(10);
+                $action!(11);
+                $action!(12);
+                $action!(13);
+                $action!(14);
+                $action!(15);
+            };
+        }
+
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        let other = manager.create_entity();
+
+        macro_rules! insert {
+            ($n:literal) => {
+                manager.insert_component(entity, Marker::<$n>);
+            };
+        }
+        for_markers!(insert);
+
+        macro_rules! check_present {
+            ($n:literal) => {
+                assert!(manager.has_component::<Marker<$n>>(entity));
+                assert!(manager.get_component::<Marker<$n>>(entity).is_some());
+                assert!(!manager.has_component::<Marker<$n>>(other));
+            };
+        }
+        for_markers!(check_present);
+
+        manager.remove_component::<Marker<3>>(entity);
+        manager.remove_component::<Marker<7>>(entity);
+        assert!(!manager.has_component::<Marker<3>>(entity));
+        assert!(!manager.has_component::<Marker<7>>(entity));
+
+        macro_rules! check_remaining {
+            ($n:literal) => {
+                if $n != 3 && $n != 7 {
+                    assert!(manager.has_component::<Marker<$n>>(entity));
+                }
+            };
+        }
+        for_markers!(check_remaining);
+    }
+
+    #[test]
+    fn entity_by_name() {
+        let mut manager = EntityComponentManager::default();
+        let player = manager.create_entity();
+        let enemy = manager.create_entity();
+        manager.set_name(player, "player");
+        manager.set_name(enemy, "enemy");
+
+        assert_eq!(manager.name(player), Some("player"));
+        assert_eq!(manager.entity_by_name("player"), Some(player));
+        assert_eq!(manager.entity_by_name("enemy"), Some(enemy));
+        assert_eq!(manager.entity_by_name("boss"), None);
+
+        manager.set_name(enemy, "player");
+        assert_eq!(manager.entity_by_name("player"), Some(enemy));
+        assert_eq!(manager.entity_by_name("enemy"), None);
+        assert_eq!(manager.name(player), None);
+
+        manager.delete_entity(enemy);
+        assert_eq!(manager.name(enemy), None);
+        assert_eq!(manager.entity_by_name("player"), None);
+    }
+
+    #[test]
+    fn dense_matches_map_storage() {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Dense(u32);
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Sparse(u32);
+
+        let mut manager = EntityComponentManager::default();
+        let early = manager.create_entity();
+        manager.insert_component(early, Dense(0));
+        manager.register_dense::<Dense>();
+
+        let entities: Vec<_> = (1..=100).map(|_| manager.create_entity()).collect();
+        for (i, entity) in entities.iter().enumerate() {
+            manager.insert_component(*entity, Dense(i as u32 + 1));
+            manager.insert_component(*entity, Sparse(i as u32 + 1));
+        }
+        manager.remove_component::<Dense>(entities[10]);
+        manager.remove_component::<Sparse>(entities[10]);
+        manager.get_component_mut::<Dense>(entities[20]).unwrap().0 = 0;
+        manager.get_component_mut::<Sparse>(entities[20]).unwrap().0 = 0;
+        manager.delete_entity(entities[30]);
+
+        let store = manager.dense_store::<Dense>().unwrap();
+        assert_eq!(store.len(), 99);
+        assert_eq!(store.components().len(), store.entities().len());
+        assert_eq!(manager.get_component::<Dense>(early), Some(&Dense(0)));
+        assert_eq!(manager.count_components::<Dense>(), 99);
+
+        let mut dense: Vec<_> = manager
+            .queue_component::<Dense>()
+            .into_iter()
+            .filter(|(entity, _)| *entity != early)
+            .map(|(entity, c)| (entity, c.0))
+            .collect();
+        let mut sparse: Vec<_> = manager
+            .queue_component::<Sparse>()
+            .into_iter()
+            .map(|(entity, c)| (entity, c.0))
+            .collect();
+        dense.sort();
+        sparse.sort();
+        assert_eq!(dense, sparse);
+
+        for entity in &entities {
+            assert_eq!(
+                manager.has_component::<Dense>(*entity),
+                manager.has_component::<Sparse>(*entity)
+            );
+            assert_eq!(
+                manager.get_component::<Dense>(*entity).map(|c| c.0),
+                manager.get_component::<Sparse>(*entity).map(|c| c.0)
+            );
+        }
+    }
+
+    #[test]
+    fn validator_rejects_invalid_components() {
+        struct Health(i32);
+
+        let mut manager = EntityComponentManager::default();
+        manager.register_validator::<Health>(|health| {
+            if health.0 < 0 {
+                Err(format!("negative health {}", health.0))
+            } else {
+                Ok(())
+            }
+        });
+        let entity = manager.create_entity();
+
+        assert!(matches!(
+            manager.try_insert_component(entity, Health(-5)),
+            Err(EcsError::ValidationFailed(_))
+        ));
+        assert!(!manager.has_component::<Health>(entity));
+
+        manager.try_insert_component(entity, Health(10)).unwrap();
+        assert_eq!(manager.get_component::<Health>(entity).unwrap().0, 10);
+    }
+
+    #[test]
+    fn get_component_checked() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 5);
+
+        assert_eq!(*manager.get_component_checked::<i32>(entity).unwrap(), 5);
+        assert!(matches!(
+            manager.get_component_checked::<f32>(entity),
+            Err(EcsError::ComponentNotFound(e, name)) if e == entity && name == "f32"
+        ));
+        assert!(matches!(
+            manager.get_component_checked::<i32>(Entity(42)),
+            Err(EcsError::EntityNotFound(e, _)) if e == Entity(42)
+        ));
+    }
+
+    #[test]
+    fn map_component() {
+        let mut manager = EntityComponentManager::default();
+        let a = manager.create_entity();
+        let b = manager.create_entity();
+        let c = manager.create_entity();
+        manager.insert_component(a, 1);
+        manager.insert_component(b, -2);
+        manager.insert_component(c, 3.0f32);
+        manager.clear_changed();
+
+        assert_eq!(manager.map_component::<i32, _>(|value| *value = -*value), 2);
+        assert_eq!(manager.get_component::<i32>(a), Some(&-1));
+        assert_eq!(manager.get_component::<i32>(b), Some(&2));
+        assert_eq!(manager.get_component::<f32>(c), Some(&3.0));
+        assert_eq!(manager.query_changed::<i32>().len(), 2);
+    }
+
+    #[test]
+    fn merge_under() {
+        #[derive(Debug, PartialEq)]
+        struct Position(i32);
+        #[derive(Debug, PartialEq)]
+        struct Velocity(i32);
+
+        let mut prefab = EntityComponentManager::default();
+        let prefab_root = prefab.create_entity();
+        let prefab_child = prefab.create_child_entity(prefab_root).unwrap();
+        prefab.insert_component(prefab_root, Position(1));
+        prefab.insert_component(prefab_child, Velocity(2));
+        prefab.set_name(prefab_child, "wheel");
+
+        let mut manager = EntityComponentManager::default();
+        let root = manager.create_entity();
+        let parent = manager.create_child_entity(root).unwrap();
+        manager.insert_component(parent, Position(0));
+
+        let merged = manager.merge_under(prefab, parent).unwrap();
+        assert_eq!(merged.len(), 2);
+        let (new_root, new_child) = (merged[0], merged[1]);
+        assert!(new_root != prefab_root && new_root != root && new_root != parent);
+
+        assert_eq!(
+            manager.get_component::<Position>(new_root),
+            Some(&Position(1))
+        );
+        assert_eq!(
+            manager.get_component::<Velocity>(new_child),
+            Some(&Velocity(2))
+        );
+        assert_eq!(
+            manager.get_component::<Position>(parent),
+            Some(&Position(0))
+        );
+        assert_eq!(*manager.get_parent(new_root).unwrap(), parent);
+        assert_eq!(*manager.get_parent(new_child).unwrap(), new_root);
+        assert_eq!(manager.entity_by_name("wheel"), Some(new_child));
+        assert_eq!(manager.count_components::<Position>(), 2);
+
+        assert!(manager
+            .merge_under(EntityComponentManager::default(), Entity(99))
+            .is_err());
+    }
+
+    #[test]
+    fn resolve_path() {
+        let mut manager = EntityComponentManager::default();
+        let root = manager.create_entity();
+        let child = manager.create_child_entity(root).unwrap();
+        let grandchild = manager.create_child_entity(child).unwrap();
+        let other = manager.create_child_entity(root).unwrap();
+        manager.set_name(root, "root");
+        manager.set_name(child, "child");
+        manager.set_name(grandchild, "grandchild");
+        manager.set_name(other, "other");
+
+        assert_eq!(
+            manager.resolve_path("root/child/grandchild"),
+            Some(grandchild)
+        );
+        assert_eq!(manager.resolve_path("root/other"), Some(other));
+        assert_eq!(manager.resolve_path("root"), Some(root));
+        assert_eq!(manager.resolve_path("root/other/grandchild"), None);
+        assert_eq!(manager.resolve_path("child/grandchild"), None);
+    }
+
+    #[test]
+    fn get_component_copy() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 7);
+
+        let value = manager.get_component_copy::<i32>(entity).unwrap();
+        manager.insert_component(entity, value + 1);
+        manager.remove_component::<i32>(entity);
+        assert_eq!(value, 7);
+        assert_eq!(manager.get_component_copy::<i32>(entity), None);
+    }
+
+    #[test]
+    fn compact() {
+        let mut manager = EntityComponentManager::default();
+        let entities: Vec<_> = (0..6).map(|_| manager.create_entity()).collect();
+        let child = manager.create_child_entity(entities[5]).unwrap();
+        for (i, entity) in entities.iter().enumerate() {
+            manager.insert_component(*entity, i as i32);
+        }
+        manager.insert_component(child, 6);
+        manager.set_name(child, "child");
+        manager.delete_entity(entities[1]);
+        manager.delete_entity(entities[3]);
+
+        let mapping = manager.compact();
+        assert_eq!(mapping.len(), 5);
+        let mut ids: Vec<_> = mapping.values().map(|entity| entity.0).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+
+        for (i, entity) in entities.iter().enumerate() {
+            if let Some(new) = mapping.get(entity) {
+                assert_eq!(manager.get_component::<i32>(*new), Some(&(i as i32)));
+            }
+        }
+        let new_child = mapping[&child];
+        assert_eq!(manager.get_component::<i32>(new_child), Some(&6));
+        assert_eq!(
+            *manager.get_parent(new_child).unwrap(),
+            mapping[&entities[5]]
+        );
+        assert_eq!(manager.entity_by_name("child"), Some(new_child));
+        assert_eq!(manager.create_entity(), Entity(6));
+    }
+
+    #[test]
+    fn component_type_count() {
+        let mut manager = EntityComponentManager::default();
+        assert_eq!(manager.component_type_count(), 0);
+        for i in 0..4 {
+            let entity = manager.create_entity();
+            manager.insert_component(entity, i);
+            manager.insert_component(entity, i as f32);
+        }
+        assert_eq!(manager.component_type_count(), 2);
+
+        manager.remove_component_from::<f32>(&[Entity(1), Entity(2), Entity(3), Entity(4)]);
+        assert_eq!(manager.component_type_count(), 1);
+    }
+
+    #[test]
+    fn delete_entity_promote_children() {
+        let mut manager = EntityComponentManager::default();
+        let root = manager.create_entity();
+        let first = manager.create_child_entity(root).unwrap();
+        let parent = manager.create_child_entity(root).unwrap();
+        let last = manager.create_child_entity(root).unwrap();
+        let child = manager.create_child_entity(parent).unwrap();
+        let grandchild = manager.create_child_entity(child).unwrap();
+        manager.insert_component(parent, 1);
+        manager.insert_component(child, 2);
+
+        manager.delete_entity_promote_children(parent).unwrap();
+        assert!(!manager.is_alive(parent));
+        assert_eq!(manager.get_component::<i32>(parent), None);
+        assert_eq!(*manager.get_parent(child).unwrap(), root);
+        assert_eq!(
+            manager.get_children(root).unwrap(),
+            &vec![first, child, last]
+        );
+        assert_eq!(*manager.get_parent(grandchild).unwrap(), child);
+        assert_eq!(manager.get_component::<i32>(child), Some(&2));
+
+        assert!(manager.delete_entity_promote_children(parent).is_err());
+    }
+
+    #[test]
+    fn iter_hierarchy() {
+        let mut manager = EntityComponentManager::default();
+        let root = manager.create_entity();
+        let detached = manager.create_entity();
+        let a = manager.create_child_entity(root).unwrap();
+        let b = manager.create_child_entity(root).unwrap();
+        let a_child = manager.create_child_entity(a).unwrap();
+        let detached_child = manager.create_child_entity(detached).unwrap();
+
+        assert_eq!(
+            manager.iter_hierarchy().collect::<Vec<_>>(),
+            vec![root, a, a_child, b, detached, detached_child]
+        );
+    }
+
+    #[test]
+    fn propagate() {
+        struct Local(i32);
+        #[derive(Clone, Debug, PartialEq)]
+        struct World(i32);
+
+        let mut manager = EntityComponentManager::default();
+        let root = manager.create_entity();
+        let child = manager.create_child_entity(root).unwrap();
+        let grandchild = manager.create_child_entity(child).unwrap();
+        let sibling = manager.create_child_entity(root).unwrap();
+        manager.insert_component(root, Local(1));
+        manager.insert_component(child, Local(10));
+        manager.insert_component(grandchild, Local(100));
+        manager.insert_component(sibling, Local(5));
+
+        manager.propagate::<Local, World, _>(|parent, local| {
+            World(parent.map_or(0, |parent| parent.0) + local.0)
+        });
+
+        assert_eq!(manager.get_component::<World>(root), Some(&World(1)));
+        assert_eq!(manager.get_component::<World>(child), Some(&World(11)));
+        assert_eq!(
+            manager.get_component::<World>(grandchild),
+            Some(&World(111))
+        );
+        assert_eq!(manager.get_component::<World>(sibling), Some(&World(6)));
+    }
+
+    #[test]
+    fn insert_component_many() {
+        #[derive(Clone, Debug, PartialEq)]
+        struct Visible(bool);
+
+        let mut manager = EntityComponentManager::default();
+        let entities: Vec<_> = (0..3).map(|_| manager.create_entity()).collect();
+        manager.insert_component_many(&entities, Visible(true));
+        assert_eq!(manager.count_components::<Visible>(), 3);
+
+        manager.get_component_mut::<Visible>(entities[1]).unwrap().0 = false;
+        assert_eq!(
+            manager.get_component::<Visible>(entities[0]),
+            Some(&Visible(true))
+        );
+        assert_eq!(
+            manager.get_component::<Visible>(entities[1]),
+            Some(&Visible(false))
+        );
+        assert_eq!(
+            manager.get_component::<Visible>(entities[2]),
+            Some(&Visible(true))
+        );
+    }
+
+    #[test]
+    fn components_cannot_be_attached_to_none() {
+        let mut manager = EntityComponentManager::default();
+        let root = manager.create_entity();
+
+        manager.insert_component(Entity::none(), 1);
+        assert_eq!(manager.get_component::<i32>(Entity::none()), None);
+        assert_eq!(manager.count_components::<i32>(), 0);
+        assert_eq!(stored_components(&manager), 0);
+        assert!(matches!(
+            manager.try_insert_component(Entity::none(), 1),
+            Err(EcsError::EntityNotFound(..))
+        ));
+        assert!(manager.entites.add_child(root, Entity::none()).is_err());
+        assert!(!manager.entites.contains(Entity::none()));
+    }
+
+    #[test]
+    fn query_filtered() {
+        struct Enemy;
+        struct Health(i32);
+
+        let mut manager = EntityComponentManager::default();
+        let weak = manager.create_entity();
+        let strong = manager.create_entity();
+        let no_health = manager.create_entity();
+        let friendly = manager.create_entity();
+        manager.insert_component(weak, Enemy);
+        manager.insert_component(weak, Health(5));
+        manager.insert_component(strong, Enemy);
+        manager.insert_component(strong, Health(50));
+        manager.insert_component(no_health, Enemy);
+        manager.insert_component(friendly, Health(1));
+
+        let targets = manager.query_filtered::<Enemy, Health, _>(|health| health.0 < 10);
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].0, weak);
+    }
+
+    #[test]
+    fn query_component_sorted() {
+        let mut manager = EntityComponentManager::default();
+        let entities: Vec<_> = (0..3).map(|_| manager.create_entity()).collect();
+        manager.insert_component(entities[2], 3);
+        manager.insert_component(entities[0], 1);
+        manager.insert_component(entities[1], 2);
+
+        let sorted: Vec<_> = manager
+            .query_component_sorted::<i32>()
+            .into_iter()
+            .map(|(entity, value)| (entity.0, *value))
+            .collect();
+        assert_eq!(sorted, vec![(1, 1), (2, 2), (3, 3)]);
+    }
+
+    #[test]
+    fn replace_component() {
+        #[derive(Debug, PartialEq)]
+        enum State {
+            Idle,
+            Running,
+        }
+
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        assert_eq!(manager.replace_component(entity, State::Idle), None);
+        assert_eq!(
+            manager.replace_component(entity, State::Running),
+            Some(State::Idle)
+        );
+        assert_eq!(
+            manager.get_component::<State>(entity),
+            Some(&State::Running)
+        );
+        assert_eq!(manager.count_components::<State>(), 1);
+    }
+
+    #[test]
+    fn query_with_parent() {
+        let mut manager = EntityComponentManager::default();
+        let root = manager.create_entity();
+        let child = manager.create_child_entity(root).unwrap();
+        let grandchild = manager.create_child_entity(child).unwrap();
+        manager.insert_component(root, 0);
+        manager.insert_component(child, 1);
+        manager.insert_component(grandchild, 2);
+
+        let mut results: Vec<_> = manager
+            .query_with_parent::<i32>()
+            .into_iter()
+            .map(|(entity, parent, value)| (entity, parent, *value))
+            .collect();
+        results.sort();
+        assert_eq!(
+            results,
+            vec![
+                (root, None, 0),
+                (child, Some(root), 1),
+                (grandchild, Some(child), 2)
+            ]
+        );
+    }
 
-pub struct EntityComponentManager {
-    component_store: ComponentStore,
-    entites: EntityTree,
-    entity_counter: Entity,
-}
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "no column for a type-erased component")]
+    fn store_insert_requires_column() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 1);
+        manager.store_insert(entity, Box::new(2.0f32));
+    }
 
-impl Default for EntityComponentManager {
-    fn default() -> Self {
-        Self {
-            component_store: HashMap::new(),
-            entites: EntityTree::default(),
-            entity_counter: Entity(0),
-        }
+    #[test]
+    fn columns_keep_types_apart() {
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 1i32);
+        manager.insert_component(entity, 1u32);
+        *manager.get_component_mut::<u32>(entity).unwrap() = 5;
+        manager.remove_component::<i32>(entity);
+
+        assert_eq!(manager.get_component::<i32>(entity), None);
+        assert_eq!(manager.get_component::<u32>(entity), Some(&5));
+        assert_eq!(manager.count_components::<i32>(), 0);
+        assert_eq!(manager.count_components::<u32>(), 1);
+        assert_eq!(manager.component_type_count(), 1);
     }
-}
 
-impl EntityComponentManager {
-    pub fn create_entity(&mut self) -> Entity {
-        self.entity_counter.0 += 1;
-        self.entites.insert_node(self.entity_counter);
-        self.entity_counter
+    #[test]
+    fn columns_behave_like_before() {
+        let mut manager = EntityComponentManager::default();
+        let a = manager.create_entity();
+        let b = manager.create_entity();
+        manager.insert_component(a, 1);
+        assert_eq!(manager.replace_component(a, 2), Some(1));
+        manager.insert_component(b, 3);
+        manager.clear_changed();
+        *manager.get_component_mut::<i32>(b).unwrap() += 1;
+
+        let mut all = manager.queue_component::<i32>();
+        all.sort();
+        assert_eq!(all, vec![(a, &2), (b, &4)]);
+        assert_eq!(manager.query_changed::<i32>(), vec![(b, &4)]);
+        assert_eq!(manager.take_component::<i32>(a), Some(2));
+        assert!(!manager.has_component::<i32>(a));
+
+        manager.delete_entity(b);
+        assert_eq!(stored_components(&manager), 0);
     }
 
-    pub fn delete_entity(&mut self, entity: Entity) {
-        self.entites.remove(entity);
-        self.component_store.retain(|(e, _), _| *e != entity);
+    #[test]
+    fn prune_empty() {
+        let mut manager = EntityComponentManager::default();
+        let root = manager.create_entity();
+        let empty = manager.create_child_entity(root).unwrap();
+        let full = manager.create_child_entity(root).unwrap();
+        let empty_leaf = manager.create_child_entity(full).unwrap();
+        manager.insert_component(full, 1);
+
+        assert_eq!(manager.empty_entities(), vec![root, empty, empty_leaf]);
+
+        manager.prune_empty();
+        assert!(manager.is_alive(root));
+        assert!(manager.is_alive(full));
+        assert!(!manager.is_alive(empty));
+        assert!(!manager.is_alive(empty_leaf));
+        assert_eq!(manager.empty_entities(), vec![root]);
     }
 
-    pub fn insert_component<T: 'static>(&mut self, entity: Entity, component: T) {
-        self.component_store.insert(
-            (entity, std::any::type_name::<T>().to_string()),
-            Box::new(component),
+    #[test]
+    fn count_with_and_any_with() {
+        let mut manager = EntityComponentManager::default();
+        assert_eq!(manager.count_with::<i32>(), 0);
+        assert!(!manager.any_with::<i32>());
+
+        for value in 0..3 {
+            let entity = manager.create_entity();
+            manager.insert_component(entity, value);
+        }
+        let disabled = manager.create_entity();
+        manager.insert_component(disabled, 3);
+        manager.set_enabled(disabled, false);
+
+        assert_eq!(
+            manager.count_with::<i32>(),
+            manager.queue_component::<i32>().len()
         );
+        assert_eq!(manager.count_with::<i32>(), 3);
+        assert!(manager.any_with::<i32>());
+        assert!(!manager.any_with::<f32>());
     }
 
-    pub fn get_component<T: 'static>(&self, entity: Entity) -> Option<&T> {
-        self.component_store
-            .get(&(entity, std::any::type_name::<T>().to_string()))
-            .and_then(|component| component.downcast_ref::<T>())
+    #[test]
+    fn iter_component() {
+        let mut manager = EntityComponentManager::default();
+        for value in 0..4 {
+            let entity = manager.create_entity();
+            manager.insert_component(entity, value);
+        }
+
+        let mut sum = 0;
+        for (_, value) in manager.iter_component::<i32>() {
+            sum += value;
+        }
+        assert_eq!(sum, 6);
+        assert_eq!(
+            manager
+                .iter_component::<i32>()
+                .filter(|(_, value)| **value % 2 == 0)
+                .count(),
+            2
+        );
+        assert_eq!(manager.iter_component::<f32>().count(), 0);
     }
 
-    pub fn get_component_mut<T: 'static>(&mut self, entity: Entity) -> Option<&mut T> {
-        self.component_store
-            .get_mut(&(entity, std::any::type_name::<T>().to_string()))
-            .and_then(|component| component.downcast_mut::<T>())
+    #[test]
+    fn dump() {
+        let mut manager = EntityComponentManager::default();
+        let root = manager.create_entity();
+        let child = manager.create_child_entity(root).unwrap();
+        manager.insert_component(child, 1i32);
+        manager.insert_component(child, 1.0f32);
+        manager.set_name(child, "player");
+
+        let dump = manager.dump();
+        let lines: Vec<_> = dump.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], format!("{} []", root));
+        assert!(lines[1].starts_with(&format!("  {} \"player\" parent={}", child, root)));
+        assert!(lines[1].contains("f32") && lines[1].contains("i32"));
     }
 
-    pub fn remove_component<T: 'static>(&mut self, entity: Entity) {
-        self.component_store
-            .remove(&(entity, std::any::type_name::<T>().to_string()));
+    #[test]
+    fn with_id_offset() {
+        let mut manager = EntityComponentManager::with_id_offset(1000);
+        assert_eq!(manager.next_entity_id(), Entity(1001));
+        assert_eq!(manager.create_entity(), Entity(1001));
+        assert_eq!(manager.next_entity_id(), Entity(1002));
+        assert_eq!(manager.entites.root, Some(Entity(1001)));
     }
 
-    pub fn queue_component<T: 'static>(&self) -> Vec<(Entity, &T)> {
-        self.component_store
-            .iter()
-            .filter(|(_, component)| component.is::<T>())
-            .map(|((entity, _), component)| (*entity, component.downcast_ref::<T>().unwrap()))
-            .collect()
+    #[test]
+    fn tags() {
+        struct Player;
+        struct Frozen;
+
+        let mut manager = EntityComponentManager::default();
+        let a = manager.create_entity();
+        let b = manager.create_entity();
+        manager.insert_tag::<Player>(a);
+        manager.insert_tag::<Player>(b);
+        manager.insert_tag::<Frozen>(b);
+
+        assert!(manager.has_tag::<Player>(a));
+        assert!(!manager.has_tag::<Frozen>(a));
+        assert_eq!(manager.query_tag::<Player>(), vec![a, b]);
+        assert_eq!(stored_components(&manager), 0);
+
+        manager.remove_tag::<Frozen>(b);
+        assert!(manager.query_tag::<Frozen>().is_empty());
+
+        manager.delete_entity(a);
+        assert!(!manager.has_tag::<Player>(a));
+        assert_eq!(manager.query_tag::<Player>(), vec![b]);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn with_components2() {
+        #[derive(Debug, PartialEq)]
+        struct Position(i32);
+        struct Velocity(i32);
+
+        let mut manager = EntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, Position(1));
+        manager.insert_component(entity, Velocity(2));
+        let still = manager.create_entity();
+        manager.insert_component(still, Position(5));
+
+        let moved =
+            manager.with_components2(entity, |position: &mut Position, velocity: &Velocity| {
+                position.0 += velocity.0;
+                position.0
+            });
+        assert_eq!(moved, Some(3));
+        assert_eq!(
+            manager.get_component::<Position>(entity),
+            Some(&Position(3))
+        );
+
+        assert_eq!(
+            manager.with_components2(still, |_: &mut Position, _: &Velocity| ()),
+            None
+        );
+        assert_eq!(
+            manager.with_components2(entity, |_: &mut Position, _: &Position| ()),
+            None
+        );
+    }
 
     #[test]
-    fn create_entity() {
+    fn configure_component_routes_to_storage() {
+        use crate::storage::Storage;
+        use std::{cell::Cell, rc::Rc};
+
+        /// Map storage that counts the inserts routed to it.
+        struct Counting(HashMap<Entity, i32>, Rc<Cell<usize>>);
+
+        impl Storage<i32> for Counting {
+            fn insert(&mut self, entity: Entity, component: i32) -> Option<i32> {
+                self.1.set(self.1.get() + 1);
+                self.0.insert(entity, component)
+            }
+            fn get(&self, entity: Entity) -> Option<&i32> {
+                self.0.get(&entity)
+            }
+            fn get_mut(&mut self, entity: Entity) -> Option<&mut i32> {
+                self.0.get_mut(&entity)
+            }
+            fn remove(&mut self, entity: Entity) -> Option<i32> {
+                self.0.remove(&entity)
+            }
+            fn iter(&self) -> Box<dyn Iterator<Item = (Entity, &i32)> + '_> {
+                Box::new(self.0.iter().map(|(entity, c)| (*entity, c)))
+            }
+            fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (Entity, &mut i32)> + '_> {
+                Box::new(self.0.iter_mut().map(|(entity, c)| (*entity, c)))
+            }
+        }
+
         let mut manager = EntityComponentManager::default();
+        let existing = manager.create_entity();
+        manager.insert_component(existing, 1);
+
+        let inserts = Rc::new(Cell::new(0));
+        manager.configure_component(ComponentConfig::with_storage(Counting(
+            HashMap::new(),
+            inserts.clone(),
+        )));
+        assert_eq!(inserts.get(), 1);
+
         let entity = manager.create_entity();
-        assert_eq!(entity, Entity(1));
+        manager.insert_component(entity, 2);
+        *manager.get_component_mut::<i32>(entity).unwrap() += 1;
+        assert_eq!(inserts.get(), 2);
+        assert_eq!(manager.get_component::<i32>(entity), Some(&3));
+        assert_eq!(manager.count_components::<i32>(), 2);
+
+        manager.remove_component::<i32>(existing);
+        assert_eq!(manager.queue_component::<i32>(), vec![(entity, &3)]);
+        assert!(manager.dense_store::<i32>().is_none());
     }
 
     #[test]
-    fn delete_entity() {
+    fn insert_requires_existing_entity() {
         let mut manager = EntityComponentManager::default();
         let entity = manager.create_entity();
+        let never_created = Entity(999);
+
+        manager.insert_component(never_created, 1);
+        manager.insert_component(entity, 2);
+        assert_eq!(manager.get_component::<i32>(never_created), None);
+        assert_eq!(manager.queue_component::<i32>(), vec![(entity, &2)]);
+        assert!(matches!(
+            manager.try_insert_component(never_created, 3),
+            Err(EcsError::EntityNotFound(e, _)) if e == never_created
+        ));
+
         manager.delete_entity(entity);
-        assert_eq!(manager.entites.len(), 0);
+        manager.insert_component(entity, 4);
+        assert_eq!(stored_components(&manager), 0);
+
+        let reserved = manager.reserve_entity();
+        manager.insert_component(reserved, 5);
+        assert_eq!(manager.get_component::<i32>(reserved), Some(&5));
     }
 
     #[test]
-    fn insert_component() {
+    fn query_component_ordered() {
         let mut manager = EntityComponentManager::default();
-        let entity = manager.create_entity();
-        manager.insert_component(entity, 1);
-        assert_eq!(manager.get_component::<i32>(entity), Some(&1));
+        let entities: Vec<Entity> = (0..5).map(|_| manager.create_entity()).collect();
+        let order = [3, 0, 4, 1, 2];
+        for index in order {
+            manager.insert_component(entities[index], index as i32);
+        }
+        manager.insert_component(entities[0], 10);
+
+        let expected: Vec<(Entity, i32)> = order
+            .iter()
+            .map(|index| {
+                (
+                    entities[*index],
+                    if *index == 0 { 10 } else { *index as i32 },
+                )
+            })
+            .collect();
+        let ordered: Vec<(Entity, i32)> = manager
+            .query_component_ordered::<i32>()
+            .into_iter()
+            .map(|(entity, value)| (entity, *value))
+            .collect();
+        assert_eq!(ordered, expected);
+
+        manager.remove_component::<i32>(entities[3]);
+        manager.insert_component(entities[3], 3);
+        assert_eq!(
+            manager.query_component_ordered::<i32>().last(),
+            Some(&(entities[3], &3))
+        );
     }
 
     #[test]
-    fn get_component() {
+    fn serialization_keeps_insertion_order() {
+        use crate::component::Component;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Position(i32);
+        impl Component for Position {}
+        impl SerializableComponent for Position {}
+
         let mut manager = EntityComponentManager::default();
-        let entity = manager.create_entity();
-        manager.insert_component(entity, 1);
-        assert_eq!(manager.get_component::<i32>(entity), Some(&1));
+        manager.register_component::<Position>(1);
+        let entities: Vec<Entity> = (0..6).map(|_| manager.create_entity()).collect();
+        for (value, entity) in entities.iter().rev().enumerate() {
+            manager.insert_component(*entity, Position(value as i32));
+        }
+        let data = manager.serialize_world().unwrap();
+        assert_eq!(data, manager.serialize_world().unwrap());
+
+        let mut loaded = EntityComponentManager::default();
+        loaded.register_component::<Position>(1);
+        loaded.deserialize_world(&data).unwrap();
+        assert_eq!(
+            loaded.query_component_ordered::<Position>(),
+            manager.query_component_ordered::<Position>()
+        );
     }
 
     #[test]
-    fn get_component_mut() {
+    fn retain_entities() {
         let mut manager = EntityComponentManager::default();
-        let entity = manager.create_entity();
-        manager.insert_component(entity, 1);
-        assert_eq!(manager.get_component_mut::<i32>(entity), Some(&mut 1));
+        let root = manager.create_entity();
+        let even = manager.create_child_entity(root).unwrap();
+        let odd = manager.create_child_entity(root).unwrap();
+        let orphan = manager.create_child_entity(odd).unwrap();
+        for entity in [root, even, odd, orphan] {
+            manager.insert_component(entity, entity.0);
+        }
+
+        manager.retain_entities(|entity| entity.0 % 2 == 0);
+
+        assert!(!manager.is_alive(root) && !manager.is_alive(odd));
+        assert_eq!(manager.get_component::<u64>(odd), None);
+        assert_eq!(manager.count_components::<u64>(), 2);
+        assert_eq!(manager.get_component::<u64>(orphan), Some(&4));
+        assert!(manager.entites.get_parent(orphan).is_err());
+        assert_eq!(manager.entites.roots(), &[even, orphan]);
+        assert_eq!(manager.entites.root, Some(even));
     }
 
     #[test]
-    fn remove_component() {
+    fn component_masks() {
         let mut manager = EntityComponentManager::default();
-        let entity = manager.create_entity();
-        manager.insert_component(entity, 1);
-        manager.remove_component::<i32>(entity);
-        assert_eq!(manager.get_component::<i32>(entity), None);
+        let a = manager.create_entity();
+        let b = manager.create_entity();
+        manager.insert_component(a, 1i32);
+
+        let int = manager.register_component_id::<i32>();
+        let float = manager.register_component_id::<f32>();
+        let text = manager.register_component_id::<String>();
+        assert_eq!(manager.register_component_id::<i32>(), int);
+
+        manager.insert_component(a, 1.0f32);
+        manager.insert_component(b, 1.0f32);
+        manager.insert_component(b, String::from("b"));
+        manager.remove_component::<f32>(b);
+
+        let masks = [
+            int.mask(),
+            float.mask(),
+            text.mask(),
+            int.mask() | float.mask(),
+            float.mask() | text.mask(),
+        ];
+        for entity in [a, b] {
+            let has = [
+                manager.has_component::<i32>(entity),
+                manager.has_component::<f32>(entity),
+                manager.has_component::<String>(entity),
+            ];
+            let expected = [has[0], has[1], has[2], has[0] && has[1], has[1] && has[2]];
+            for (mask, expected) in masks.iter().zip(expected) {
+                assert_eq!(manager.matches_mask(entity, *mask), expected);
+            }
+        }
+        assert!(manager.matches_mask(a, int.mask() | float.mask()));
+
+        manager.delete_entity(a);
+        assert!(!manager.matches_mask(a, int.mask()));
+        assert!(manager.matches_mask(a, 0));
     }
 
     #[test]
-    fn get_component_with_different_type() {
+    fn clear_entity_components() {
+        struct Marker;
+
         let mut manager = EntityComponentManager::default();
         let entity = manager.create_entity();
-        manager.insert_component(entity, 1);
-        assert_eq!(manager.get_component::<f32>(entity), None);
+        let child = manager.create_child_entity(entity).unwrap();
+        manager.insert_component(entity, 1i32);
+        manager.insert_component(entity, 1.0f32);
+        manager.insert_component(entity, String::from("a"));
+        manager.insert_tag::<Marker>(entity);
+        manager.insert_component(child, 2i32);
+
+        manager.clear_entity_components(entity);
+        assert!(manager.is_alive(entity));
+        assert!(manager.components_of(entity).is_empty());
+        assert!(!manager.has_tag::<Marker>(entity));
+        assert_eq!(manager.get_children(entity).unwrap(), &vec![child]);
+        assert_eq!(manager.get_component::<i32>(child), Some(&2));
     }
 
     #[test]
-    fn get_component_mut_with_different_type() {
+    fn entity_json_round_trip() {
+        use crate::component::Component;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Position(i32);
+        impl Component for Position {}
+        impl SerializableComponent for Position {}
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Health(u32);
+        impl Component for Health {}
+        impl SerializableComponent for Health {}
+
         let mut manager = EntityComponentManager::default();
-        let entity = manager.create_entity();
-        manager.insert_component(entity, 1);
-        assert_eq!(manager.get_component_mut::<f32>(entity), None);
+        manager.register_component::<Position>(1);
+        manager.register_component::<Health>(1);
+        let root = manager.create_entity();
+        let prefab = manager.create_child_entity(root).unwrap();
+        let part = manager.create_child_entity(prefab).unwrap();
+        manager.insert_component(prefab, Position(1));
+        manager.insert_component(prefab, Health(10));
+        manager.insert_component(part, Position(2));
+
+        let json = manager.entity_to_json(prefab, true).unwrap();
+        let parent = manager.create_entity();
+        let spawned = manager.spawn_from_json(&json, Some(parent)).unwrap();
+
+        assert_ne!(spawned, prefab);
+        assert_eq!(manager.entites.get_parent(spawned).unwrap(), &parent);
+        assert_eq!(
+            manager.get_component::<Position>(spawned),
+            Some(&Position(1))
+        );
+        assert_eq!(manager.get_component::<Health>(spawned), Some(&Health(10)));
+        let children = manager.get_children(spawned).unwrap().clone();
+        assert_eq!(children.len(), 1);
+        assert_eq!(
+            manager.get_component::<Position>(children[0]),
+            Some(&Position(2))
+        );
+
+        let shallow = manager.entity_to_json(prefab, false).unwrap();
+        let alone = manager.spawn_from_json(&shallow, None).unwrap();
+        assert!(!manager.has_children(alone));
+        assert!(manager.entity_to_json(Entity(999), false).is_err());
+
+        let mut unregistered = EntityComponentManager::default();
+        let before = unregistered.next_entity_id();
+        assert!(unregistered.spawn_from_json(&json, None).is_err());
+        assert_eq!(unregistered.next_entity_id(), before);
     }
 
     #[test]
-    fn remove_component_with_different_type() {
+    fn query2_mut() {
+        #[derive(Debug, PartialEq)]
+        struct Position(i32);
+        struct Velocity(i32);
+
         let mut manager = EntityComponentManager::default();
-        let entity = manager.create_entity();
-        manager.insert_component(entity, 1);
-        manager.remove_component::<f32>(entity);
-        assert_eq!(manager.get_component::<i32>(entity), Some(&1));
+        let moving: Vec<Entity> = (1..=3)
+            .map(|speed| {
+                let entity = manager.create_entity();
+                manager.insert_component(entity, Position(0));
+                manager.insert_component(entity, Velocity(speed));
+                entity
+            })
+            .collect();
+        let still = manager.create_entity();
+        manager.insert_component(still, Position(0));
+
+        for _ in 0..2 {
+            for (_, position, velocity) in manager.query2_mut::<Position, Velocity>() {
+                position.0 += velocity.0;
+            }
+        }
+        for (speed, entity) in (1..).zip(moving) {
+            assert_eq!(manager.get_component(entity), Some(&Position(2 * speed)));
+        }
+        assert_eq!(manager.get_component(still), Some(&Position(0)));
+        assert!(manager.query2_mut::<Position, Position>().is_empty());
     }
 
     #[test]
-    fn get_component_with_different_entity() {
+    fn diff() {
+        let mut before = EntityComponentManager::default();
+        let shared = before.create_entity();
+        let removed = before.create_entity();
+        before.insert_component(shared, 1i32);
+        before.insert_component(shared, 1.0f32);
+
+        let mut after = EntityComponentManager::default();
+        after.create_entity();
+        after.create_entity();
+        let added = after.create_entity();
+        after.delete_entity(removed);
+        after.insert_component(shared, 2i32);
+        after.insert_component(shared, 1u8);
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.added_entities, vec![added]);
+        assert_eq!(diff.removed_entities, vec![removed]);
+        assert_eq!(diff.changed_components.len(), 1);
+        assert_eq!(
+            diff.changed_components[&shared],
+            ComponentChanges {
+                added: vec!["u8"],
+                removed: vec!["f32"],
+            }
+        );
+        assert!(before.diff(&before).is_empty());
+    }
+
+    #[test]
+    fn get_component_or_err() {
         let mut manager = EntityComponentManager::default();
         let entity = manager.create_entity();
-        manager.insert_component(entity, 1);
-        assert_eq!(manager.get_component::<i32>(Entity(2)), None);
+        manager.insert_component(entity, 1i32);
+
+        assert_eq!(manager.get_component_or_err::<i32>(entity).unwrap(), &1);
+        assert!(matches!(
+            manager.get_component_or_err::<i32>(Entity(999)),
+            Err(EcsError::EntityNotFound(e, _)) if e == Entity(999)
+        ));
+        assert!(matches!(
+            manager.get_component_or_err::<f32>(entity),
+            Err(EcsError::ComponentNotFound(e, "f32")) if e == entity
+        ));
+
+        // Corrupt the store so the `u8` key holds a column of another type.
+        let mut column = Column::<f32>::new();
+        column.insert(entity, 1.0, 0);
+        manager
+            .component_store
+            .insert(TypeId::of::<u8>(), Box::new(column));
+        assert!(matches!(
+            manager.get_component_or_err::<u8>(entity),
+            Err(EcsError::ComponentTypeMismatch(e, "u8")) if e == entity
+        ));
     }
 
     #[test]
-    fn get_component_mut_with_different_entity() {
+    fn clear() {
+        struct Marker;
+
         let mut manager = EntityComponentManager::default();
+        manager.register_dense::<f32>();
+        assert!(manager.is_empty());
+        let root = manager.create_entity();
+        let child = manager.create_child_entity(root).unwrap();
+        manager.insert_component(root, 1i32);
+        manager.insert_component(child, 1.0f32);
+        manager.insert_tag::<Marker>(child);
+        manager.set_name(child, "child");
+        manager.reserve_entity();
+        assert_eq!(manager.len(), 2);
+        assert!(!manager.is_empty());
+
+        manager.clear();
+        assert!(manager.is_empty());
+        assert_eq!(manager.len(), 0);
+        assert_eq!(stored_components(&manager), 0);
+        assert_eq!(manager.entity_by_name("child"), None);
+        assert_eq!(manager.entites.root, None);
+
         let entity = manager.create_entity();
-        manager.insert_component(entity, 1);
-        assert_eq!(manager.get_component_mut::<i32>(Entity(2)), None);
+        assert_eq!(entity, Entity(1));
+        assert_eq!(manager.entites.root, Some(entity));
+        assert_eq!(manager.get_component::<i32>(entity), None);
+        assert!(!manager.has_tag::<Marker>(entity));
+        manager.insert_component(entity, 2.0f32);
+        assert!(manager.dense_store::<f32>().is_some());
+        assert_eq!(manager.queue_component::<f32>(), vec![(entity, &2.0)]);
     }
 
     #[test]
-    fn remove_component_with_different_entity() {
+    fn insert_or_merge() {
+        #[derive(Debug, PartialEq)]
+        struct DamageBuffer(u32);
+        impl MergeableComponent for DamageBuffer {
+            fn merge(&mut self, incoming: Self) {
+                self.0 += incoming.0;
+            }
+        }
+
         let mut manager = EntityComponentManager::default();
         let entity = manager.create_entity();
-        manager.insert_component(entity, 1);
-        manager.remove_component::<i32>(Entity(2));
-        assert_eq!(manager.get_component::<i32>(entity), Some(&1));
+        manager.insert_or_merge(entity, DamageBuffer(3));
+        manager.insert_or_merge(entity, DamageBuffer(4));
+        assert_eq!(manager.get_component(entity), Some(&DamageBuffer(7)));
+        assert_eq!(manager.count_components::<DamageBuffer>(), 1);
+
+        manager.insert_component(entity, DamageBuffer(1));
+        assert_eq!(manager.get_component(entity), Some(&DamageBuffer(1)));
     }
 
     #[test]
-    fn remove_entity_with_component() {
+    fn restore_drops_reserved_entities() {
         let mut manager = EntityComponentManager::default();
+        manager.create_entity();
+        let snapshot = manager.snapshot().unwrap();
+        let reserved = manager.reserve_entity();
+        manager.insert_component(reserved, 1);
+
+        manager.restore(&snapshot).unwrap();
+        // The id is free again, it must not inherit anything from the reservation.
+        let created = manager.create_entity();
+        assert_eq!(created, reserved);
+        assert_eq!(manager.get_component::<i32>(created), None);
+        manager.flush_reserved();
+        assert_eq!(manager.len(), 2);
+    }
+
+    #[test]
+    fn despawn_hooks_skip_unknown_entities() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let mut manager = EntityComponentManager::default();
+        let despawned = Rc::new(RefCell::new(Vec::new()));
+        let log = despawned.clone();
+        manager.on_despawn(move |entity| log.borrow_mut().push(entity));
+
         let entity = manager.create_entity();
-        manager.insert_component(entity, 1);
+        manager.delete_entity(Entity(999));
         manager.delete_entity(entity);
-        assert_eq!(manager.get_component::<i32>(entity), None);
-        assert_eq!(manager.component_store.len(), 0);
+        manager.delete_entity(entity);
+        assert_eq!(*despawned.borrow(), vec![entity]);
     }
 
     #[test]
-    fn add_struct_component() {
-        #[derive(Debug, PartialEq)]
-        struct Component {
-            value: i32,
-        }
+    fn tags_count_as_content_and_survive_restore() {
+        struct Marker;
 
         let mut manager = EntityComponentManager::default();
-        let entity = manager.create_entity();
-        manager.insert_component(entity, Component { value: 1 });
+        let root = manager.create_entity();
+        let tagged = manager.create_child_entity(root).unwrap();
+        let empty = manager.create_child_entity(root).unwrap();
+        manager.insert_tag::<Marker>(tagged);
+        let snapshot = manager.snapshot().unwrap();
+
+        assert_eq!(manager.empty_entities(), vec![root, empty]);
+        manager.prune_empty();
+        assert!(manager.is_alive(tagged) && !manager.is_alive(empty));
+
+        manager.restore(&snapshot).unwrap();
+        assert!(manager.is_alive(tagged));
+        assert!(manager.has_tag::<Marker>(tagged));
+        assert_eq!(manager.query_tag::<Marker>(), vec![tagged]);
+
+        let json = manager.serialize_world().unwrap();
+        manager.deserialize_world(&json).unwrap();
+        assert!(!manager.has_tag::<Marker>(tagged));
+    }
+
+    #[test]
+    fn compact_keeps_offset() {
+        let mut manager = EntityComponentManager::with_id_offset(1000);
+        let first = manager.create_entity();
+        let second = manager.create_entity();
+        let third = manager.create_entity();
+        manager.insert_component(third, 3);
+        manager.delete_entity(second);
+
+        let mapping = manager.compact();
+        assert_eq!(mapping[&first], Entity(1001));
+        assert_eq!(mapping[&third], Entity(1002));
+        assert_eq!(manager.get_component::<i32>(Entity(1002)), Some(&3));
+        assert_eq!(manager.create_entity(), Entity(1003));
+
+        manager.clear();
+        assert_eq!(manager.create_entity(), Entity(1001));
+    }
+
+    #[test]
+    fn set_root_detaches_child() {
+        let mut manager = EntityComponentManager::default();
+        let root = manager.create_entity();
+        let child = manager.create_child_entity(root).unwrap();
+        manager.set_root(child);
+
+        assert_eq!(manager.root(), Some(child));
         assert_eq!(
-            manager.get_component::<Component>(entity),
-            Some(&Component { value: 1 })
+            manager.iter_hierarchy().collect::<Vec<_>>(),
+            vec![child, root]
+        );
+        assert_eq!(manager.dump().lines().count(), 2);
+        assert!(!manager.dump().contains("parent="));
+    }
+
+    #[test]
+    fn spawn_with_id_rejects_reserved() {
+        let mut manager = EntityComponentManager::default();
+        manager.create_entity();
+        let reserved = manager.reserve_entity();
+
+        assert!(matches!(
+            manager.spawn_with_id(reserved),
+            Err(EcsError::EntityAlreadyExists(id)) if id == reserved
+        ));
+        assert!(manager.create_child_entity(reserved).is_err());
+        manager.flush_reserved();
+        let child = manager.create_child_entity(reserved).unwrap();
+        assert!(manager.spawn_with_id(reserved).is_err());
+        assert_eq!(manager.entites.get_parent(child).unwrap(), &reserved);
+    }
+
+    #[test]
+    fn delete_reserved_before_flush() {
+        let mut manager = EntityComponentManager::default();
+        let root = manager.create_entity();
+        let reserved = manager.reserve_entity();
+        let kept = manager.reserve_entity();
+        manager.delete_entity(reserved);
+        manager.flush_reserved();
+
+        assert!(!manager.is_alive(reserved));
+        assert!(manager.is_alive(kept));
+        assert_eq!(
+            manager.iter_hierarchy().collect::<Vec<_>>(),
+            vec![root, kept]
         );
     }
+
+    #[test]
+    fn merge_under_keeps_storage() {
+        let mut manager = EntityComponentManager::default();
+        let parent = manager.create_entity();
+        let mut other = EntityComponentManager::default();
+        other.register_dense::<u32>();
+        let entity = other.create_entity();
+        other.insert_component(entity, 7u32);
+
+        let merged = manager.merge_under(other, parent).unwrap();
+
+        let dense = manager.dense_store::<u32>().unwrap();
+        assert_eq!(dense.get(merged[0]), Some(&7));
+        assert_eq!(dense.len(), 1);
+    }
+
+    #[test]
+    fn map_component_skips_unqueryable() {
+        let mut manager = EntityComponentManager::default();
+        let active = manager.create_entity();
+        let disabled = manager.create_entity();
+        let marked = manager.create_entity();
+        for entity in [active, disabled, marked] {
+            manager.insert_component(entity, 1);
+        }
+        manager.set_enabled(disabled, false);
+        manager.mark_for_deletion(marked);
+        manager.clear_changed();
+
+        assert_eq!(manager.map_component::<i32, _>(|value| *value += 1), 1);
+        assert_eq!(manager.get_component::<i32>(active), Some(&2));
+        assert_eq!(manager.get_component::<i32>(disabled), Some(&1));
+        assert_eq!(manager.get_component::<i32>(marked), Some(&1));
+        assert_eq!(manager.query_changed::<i32>(), vec![(active, &2)]);
+    }
 }
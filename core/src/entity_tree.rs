@@ -1,64 +1,116 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
 
 use crate::{
     entity::Entity,
     error::{EcsError, FindEntityLocation},
 };
 
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct EntityTree {
     pub root: Option<Entity>,
+    /// Top-level subtrees visited in order by the tree iterator, including `root`.
+    #[serde(default)]
+    pub roots: Vec<Entity>,
 
     pub children: BTreeMap<Entity, Vec<Entity>>,
     pub parent: BTreeMap<Entity, Option<Entity>>,
-}
 
-impl Default for EntityTree {
-    fn default() -> Self {
-        Self {
-            root: None,
-            children: BTreeMap::new(),
-            parent: BTreeMap::new(),
-        }
-    }
+    /// Nodes waiting to be removed by [`EntityTree::compact`].
+    #[serde(default)]
+    pub marked: BTreeSet<Entity>,
 }
 
 impl EntityTree {
-    pub fn new(root_entity: Entity) -> Self {
-        let mut tree = Self::default();
-        if root_entity.is_some() {
-            tree.insert_node(root_entity);
-            tree.set_root(root_entity);
+    /// A tree holding only `root_entity`, fails with `NoRootEntity` for `Entity::none()`.
+    pub fn new(root_entity: Entity) -> Result<Self, EcsError> {
+        if root_entity.is_none() {
+            return Err(EcsError::NoRootEntity);
         }
-        tree
+        let mut tree = Self::default();
+        tree.insert_node(root_entity);
+        tree.set_root(root_entity);
+        Ok(tree)
     }
 
+    /// Makes `entity` the root and the first top-level subtree, earlier roots stay top-level.
+    ///
+    /// An attached `entity` is detached from its parent first and keeps its subtree.
     pub fn set_root(&mut self, entity: Entity) {
+        self.detach(entity);
+        self.roots.retain(|root| *root != entity);
+        self.roots.insert(0, entity);
         self.root = Some(entity);
     }
 
-    pub fn add_child(&mut self, parent: Entity, child: Entity) -> Result<(), EcsError> {
-        if parent.is_none() && self.root.is_none() {
-            return Err(EcsError::NoRootEntity);
+    /// Adds another top-level subtree, becoming the root if there is none yet.
+    ///
+    /// An attached `entity` is detached from its parent first and keeps its subtree.
+    pub fn add_root(&mut self, entity: Entity) {
+        if !self.contains(entity) {
+            self.insert_node(entity);
+        }
+        self.detach(entity);
+        if !self.roots.contains(&entity) {
+            self.roots.push(entity);
         }
+        if self.root.is_none() {
+            self.root = Some(entity);
+        }
+    }
 
-        if let Some(p) = self.children.get_mut(&parent) {
-            p.push(child);
-        } else if self.root.is_some() {
-            if parent == self.root.unwrap() {
-                return Err(EcsError::EntityNotFound(
-                    parent,
-                    FindEntityLocation::EntityTree,
-                ));
-            }
-            return self.add_child(self.root.unwrap(), child);
-        } else {
+    /// Unlinks `entity` from its parent, leaving it parentless with its subtree intact.
+    fn detach(&mut self, entity: Entity) {
+        let Some(parent) = self.parent.get_mut(&entity).and_then(Option::take) else {
+            return;
+        };
+        if let Some(siblings) = self.children.get_mut(&parent) {
+            siblings.retain(|sibling| *sibling != entity);
+        }
+    }
+
+    pub fn roots(&self) -> &[Entity] {
+        &self.roots
+    }
+
+    pub fn add_child(&mut self, parent: Entity, child: Entity) -> Result<(), EcsError> {
+        if child.is_none() {
             return Err(EcsError::EntityNotFound(
-                parent,
+                child,
                 FindEntityLocation::EntityTree,
             ));
         }
+        if parent == child {
+            return Err(EcsError::SelfParent(child));
+        }
+        if !self.children.contains_key(&parent) {
+            match self.root {
+                // The root may have been set without being inserted as a node yet.
+                Some(root) if root == parent => self.insert_node(root),
+                // Unknown parents fall back to the root.
+                Some(root) => return self.add_child(root, child),
+                None if parent.is_none() => return Err(EcsError::NoRootEntity),
+                None => {
+                    return Err(EcsError::EntityNotFound(
+                        parent,
+                        FindEntityLocation::EntityTree,
+                    ))
+                }
+            }
+        }
+        if self.is_descendant_or_self(parent, child) {
+            return Err(EcsError::HierarchyCycle(child));
+        }
+        // Attached children have to be moved with `reparent` instead.
+        if self.parent.get(&child).copied().flatten().is_some() {
+            return Err(EcsError::EntityAlreadyExists(child));
+        }
+
+        self.children.entry(parent).or_default().push(child);
         self.parent.insert(child, Some(parent));
-        self.children.insert(child, Vec::new());
+        self.roots.retain(|root| *root != child);
+        self.children.entry(child).or_default();
         Ok(())
     }
 
@@ -69,6 +121,15 @@ impl EntityTree {
         ))
     }
 
+    /// Number of direct children, 0 for leaves and unknown entities.
+    pub fn child_count(&self, entity: Entity) -> usize {
+        self.children.get(&entity).map_or(0, Vec::len)
+    }
+
+    pub fn has_children(&self, entity: Entity) -> bool {
+        self.child_count(entity) > 0
+    }
+
     pub fn get_parent(&self, entity: Entity) -> Result<&Entity, EcsError> {
         self.parent
             .get(&entity)
@@ -84,13 +145,384 @@ impl EntityTree {
         self.parent.insert(entity, None);
     }
 
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.children.contains_key(&entity)
+    }
+
     pub fn len(&self) -> usize {
         self.children.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Removes `entity` from the tree and detaches it from its parent.
+    ///
+    /// Children of `entity` stay in the tree without a parent.
     pub fn remove(&mut self, entity: Entity) {
-        self.children.remove(&entity);
-        self.parent.remove(&entity);
+        if let Some(Some(parent)) = self.parent.remove(&entity) {
+            if let Some(siblings) = self.children.get_mut(&parent) {
+                siblings.retain(|sibling| *sibling != entity);
+            }
+        }
+        for child in self.children.remove(&entity).unwrap_or_default() {
+            if let Some(parent) = self.parent.get_mut(&child) {
+                *parent = None;
+            }
+        }
+        self.roots.retain(|root| *root != entity);
+        if self.root == Some(entity) {
+            self.root = self.roots.first().copied();
+        }
+        self.marked.remove(&entity);
+    }
+
+    /// Groups all nodes into their connected subtrees.
+    ///
+    /// Every node without a parent starts a group containing itself and all of its
+    /// descendants in pre-order.
+    pub fn connected_components(&self) -> Vec<Vec<Entity>> {
+        self.parent
+            .iter()
+            .filter(|(_, parent)| parent.is_none())
+            .map(|(&top, _)| {
+                let mut group = Vec::new();
+                let mut stack = vec![top];
+                while let Some(entity) = stack.pop() {
+                    group.push(entity);
+                    if let Some(children) = self.children.get(&entity) {
+                        stack.extend(children.iter().rev());
+                    }
+                }
+                group
+            })
+            .collect()
+    }
+
+    pub fn next_sibling(&self, entity: Entity) -> Option<Entity> {
+        let (siblings, index) = self.sibling_position(entity)?;
+        siblings.get(index + 1).copied()
+    }
+
+    pub fn prev_sibling(&self, entity: Entity) -> Option<Entity> {
+        let (siblings, index) = self.sibling_position(entity)?;
+        index.checked_sub(1).map(|index| siblings[index])
+    }
+
+    fn sibling_position(&self, entity: Entity) -> Option<(&Vec<Entity>, usize)> {
+        let parent = self.get_parent(entity).ok()?;
+        let siblings = self.children.get(parent)?;
+        let index = siblings.iter().position(|s| *s == entity)?;
+        Some((siblings, index))
+    }
+
+    /// Marks `entity` for removal by the next [`Self::compact`].
+    pub fn mark_for_deletion(&mut self, entity: Entity) {
+        if self.contains(entity) {
+            self.marked.insert(entity);
+        }
+    }
+
+    pub fn is_marked(&self, entity: Entity) -> bool {
+        self.marked.contains(&entity)
+    }
+
+    /// Removes all marked nodes in a single pass and returns them.
+    ///
+    /// Each affected children vector is rebuilt once, no matter how many of its
+    /// entries were marked. Surviving children of removed nodes lose their parent.
+    pub fn compact(&mut self) -> Vec<Entity> {
+        let marked = std::mem::take(&mut self.marked);
+        let mut affected_parents = BTreeSet::new();
+
+        for entity in &marked {
+            if let Some(Some(parent)) = self.parent.remove(entity) {
+                affected_parents.insert(parent);
+            }
+            for child in self.children.remove(entity).unwrap_or_default() {
+                if let Some(parent) = self.parent.get_mut(&child) {
+                    *parent = None;
+                }
+            }
+        }
+        for parent in affected_parents {
+            if let Some(children) = self.children.get_mut(&parent) {
+                children.retain(|child| !marked.contains(child));
+            }
+        }
+        self.roots.retain(|root| !marked.contains(root));
+        if self.root.is_some_and(|root| marked.contains(&root)) {
+            self.root = self.roots.first().copied();
+        }
+        marked.into_iter().collect()
+    }
+
+    /// Moves `entity` with its subtree to the end of `new_parent`'s children.
+    pub fn reparent(&mut self, entity: Entity, new_parent: Entity) -> Result<(), EcsError> {
+        self.reparent_at(entity, new_parent, usize::MAX)
+    }
+
+    /// Moves `entity` with its subtree to position `index` of `new_parent`'s children.
+    ///
+    /// `index` is clamped to the number of children. Fails with `HierarchyCycle` if
+    /// `new_parent` is `entity` or one of its descendants.
+    pub fn reparent_at(
+        &mut self,
+        entity: Entity,
+        new_parent: Entity,
+        index: usize,
+    ) -> Result<(), EcsError> {
+        for e in [entity, new_parent] {
+            if !self.contains(e) {
+                return Err(EcsError::EntityNotFound(e, FindEntityLocation::EntityTree));
+            }
+        }
+        if self.is_descendant_or_self(new_parent, entity) {
+            return Err(EcsError::HierarchyCycle(entity));
+        }
+
+        if let Some(Some(old_parent)) = self.parent.get(&entity) {
+            if let Some(siblings) = self.children.get_mut(old_parent) {
+                siblings.retain(|sibling| *sibling != entity);
+            }
+        }
+        let siblings = self.children.entry(new_parent).or_default();
+        siblings.insert(index.min(siblings.len()), entity);
+        self.parent.insert(entity, Some(new_parent));
+        self.roots.retain(|root| *root != entity);
+        Ok(())
+    }
+
+    /// Returns whether `entity` is `ancestor` or lies somewhere below it.
+    fn is_descendant_or_self(&self, entity: Entity, ancestor: Entity) -> bool {
+        let mut current = Some(entity);
+        while let Some(node) = current {
+            if node == ancestor {
+                return true;
+            }
+            current = self.parent.get(&node).copied().flatten();
+        }
+        false
+    }
+
+    /// Pre-order traversal from the root yielding each entity with its depth.
+    ///
+    /// The root is depth 0, nodes deeper than `max_depth` are never visited.
+    pub fn iter_depth_limited(
+        &self,
+        max_depth: usize,
+    ) -> impl Iterator<Item = (Entity, usize)> + '_ {
+        let mut stack: Vec<(Entity, usize)> = self.root.map(|root| (root, 0)).into_iter().collect();
+        std::iter::from_fn(move || {
+            let (entity, depth) = stack.pop()?;
+            if depth < max_depth {
+                if let Some(children) = self.children.get(&entity) {
+                    stack.extend(children.iter().rev().map(|child| (*child, depth + 1)));
+                }
+            }
+            Some((entity, depth))
+        })
+    }
+
+    /// Iterates the parent of `entity`, then its grandparent, up to the top of its tree.
+    pub fn ancestors(&self, entity: Entity) -> impl Iterator<Item = Entity> + '_ {
+        let mut current = entity;
+        std::iter::from_fn(move || {
+            current = self.parent.get(&current).copied().flatten()?;
+            Some(current)
+        })
+    }
+
+    /// Pre-order traversal of the subtree rooted at `start`, empty if `start` is not in the tree.
+    pub fn iter_from(&self, start: Entity) -> EntityTreeIterator<'_> {
+        EntityTreeIterator {
+            tree: self,
+            start: Some(start).filter(|start| self.contains(*start)),
+            pending: Vec::new(),
+            current: None,
+            visited: 0,
+        }
+    }
+
+    /// Applies `f` to every node of the tree.
+    ///
+    /// With the `rayon` feature each direct child of the root is processed with its
+    /// subtree on a separate task, without it the subtrees are visited sequentially.
+    pub fn par_for_each_subtree<F: Fn(Entity) + Sync>(&self, f: F) {
+        let Some(root) = self.root else {
+            return;
+        };
+        f(root);
+        let subtrees = self
+            .children
+            .get(&root)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+            subtrees
+                .par_iter()
+                .for_each(|subtree| self.iter_from(*subtree).for_each(&f));
+        }
+        #[cfg(not(feature = "rayon"))]
+        subtrees
+            .iter()
+            .for_each(|subtree| self.iter_from(*subtree).for_each(&f));
+    }
+
+    /// Post-order traversal from the root, every node comes after all of its descendants.
+    pub fn iter_post_order(&self) -> impl Iterator<Item = Entity> + '_ {
+        // Each frame holds a node and the index of the next child to descend into.
+        let mut stack: Vec<(Entity, usize)> = self.root.map(|root| (root, 0)).into_iter().collect();
+        std::iter::from_fn(move || {
+            while let Some((node, next_child)) = stack.last_mut() {
+                let child = self
+                    .children
+                    .get(node)
+                    .and_then(|children| children.get(*next_child))
+                    .copied();
+                match child {
+                    Some(child) => {
+                        *next_child += 1;
+                        stack.push((child, 0));
+                    }
+                    None => {
+                        let node = *node;
+                        stack.pop();
+                        return Some(node);
+                    }
+                }
+            }
+            None
+        })
+    }
+
+    /// Renames every node through `mapping`, nodes missing from it keep their id.
+    pub fn remap(&mut self, mapping: &HashMap<Entity, Entity>) {
+        let map = |entity: Entity| mapping.get(&entity).copied().unwrap_or(entity);
+        self.root = self.root.map(map);
+        self.roots = self.roots.iter().copied().map(map).collect();
+        self.children = std::mem::take(&mut self.children)
+            .into_iter()
+            .map(|(entity, children)| (map(entity), children.into_iter().map(map).collect()))
+            .collect();
+        self.parent = std::mem::take(&mut self.parent)
+            .into_iter()
+            .map(|(entity, parent)| (map(entity), parent.map(map)))
+            .collect();
+        self.marked = std::mem::take(&mut self.marked)
+            .into_iter()
+            .map(map)
+            .collect();
+    }
+
+    /// All nodes exactly `depth` levels below the root, the root is depth 0.
+    pub fn nodes_at_depth(&self, depth: usize) -> Vec<Entity> {
+        let mut level: Vec<Entity> = self.root.into_iter().collect();
+        for _ in 0..depth {
+            if level.is_empty() {
+                break;
+            }
+            level = level
+                .iter()
+                .filter_map(|node| self.children.get(node))
+                .flatten()
+                .copied()
+                .collect();
+        }
+        level
+    }
+
+    /// Cuts `entity` and its descendants out of the tree and returns them as a tree
+    /// rooted at `entity`.
+    pub fn detach_subtree(&mut self, entity: Entity) -> Result<EntityTree, EcsError> {
+        if !self.contains(entity) {
+            return Err(EcsError::EntityNotFound(
+                entity,
+                FindEntityLocation::EntityTree,
+            ));
+        }
+        let nodes: Vec<Entity> = self.iter_from(entity).collect();
+        if let Some(Some(parent)) = self.parent.get(&entity) {
+            if let Some(siblings) = self.children.get_mut(parent) {
+                siblings.retain(|sibling| *sibling != entity);
+            }
+        }
+
+        let mut detached = EntityTree::new(entity)?;
+        for node in nodes {
+            if let Some(children) = self.children.remove(&node) {
+                detached.children.insert(node, children);
+            }
+            if let Some(parent) = self.parent.remove(&node) {
+                detached
+                    .parent
+                    .insert(node, parent.filter(|_| node != entity));
+            }
+            if self.marked.remove(&node) {
+                detached.marked.insert(node);
+            }
+            self.roots.retain(|root| *root != node);
+            if self.root == Some(node) {
+                self.root = None;
+            }
+        }
+        if self.root.is_none() {
+            self.root = self.roots.first().copied();
+        }
+        Ok(detached)
+    }
+
+    /// Pre-order list of every node with its parent and depth, in iteration order.
+    pub fn flatten(&self) -> Vec<(Entity, Option<Entity>, usize)> {
+        let tops = self
+            .root
+            .into_iter()
+            .chain(self.roots.iter().copied().filter(|r| Some(*r) != self.root));
+        let mut stack: Vec<(Entity, Option<Entity>, usize)> =
+            tops.map(|top| (top, None, 0)).collect();
+        stack.reverse();
+
+        let mut flat = Vec::with_capacity(self.len());
+        while let Some((entity, parent, depth)) = stack.pop() {
+            flat.push((entity, parent, depth));
+            if let Some(children) = self.children.get(&entity) {
+                stack.extend(
+                    children
+                        .iter()
+                        .rev()
+                        .map(|child| (*child, Some(entity), depth + 1)),
+                );
+            }
+        }
+        flat
+    }
+
+    /// Moves `subtree_root` under `new_parent`, its descendants keep their structure.
+    ///
+    /// Same as [`Self::reparent`], which never touches the links below the moved node.
+    pub fn move_subtree(
+        &mut self,
+        subtree_root: Entity,
+        new_parent: Entity,
+    ) -> Result<(), EcsError> {
+        self.reparent(subtree_root, new_parent)
+    }
+
+    /// The deepest node that is `a` or one of its ancestors and also `b` or one of its ancestors.
+    ///
+    /// `None` if either entity is not in the tree or they lie in different subtrees.
+    pub fn common_ancestor(&self, a: Entity, b: Entity) -> Option<Entity> {
+        if !self.contains(a) || !self.contains(b) {
+            return None;
+        }
+        let chain: BTreeSet<Entity> = std::iter::once(a).chain(self.ancestors(a)).collect();
+        std::iter::once(b)
+            .chain(self.ancestors(b))
+            .find(|node| chain.contains(node))
     }
 }
 
@@ -99,22 +531,52 @@ impl<'a> IntoIterator for &'a EntityTree {
     type IntoIter = EntityTreeIterator<'a>;
 
     fn into_iter(self) -> Self::IntoIter {
+        // Remaining roots are popped from the back.
+        let mut pending: Vec<Entity> = self
+            .roots
+            .iter()
+            .rev()
+            .copied()
+            .filter(|root| Some(*root) != self.root)
+            .collect();
         EntityTreeIterator {
             tree: self,
+            start: self.root.or_else(|| pending.pop()),
+            pending,
             current: None,
+            visited: 0,
         }
     }
 }
 
 pub struct EntityTreeIterator<'a> {
     tree: &'a EntityTree,
+    start: Option<Entity>,
+    pending: Vec<Entity>,
     current: Option<Entity>,
+    /// Nodes yielded so far, used for the size hint.
+    visited: usize,
 }
 
 impl<'a> Iterator for EntityTreeIterator<'a> {
     type Item = Entity;
 
     fn next(&mut self) -> Option<Self::Item> {
+        let next = self.advance();
+        if next.is_some() {
+            self.visited += 1;
+        }
+        next
+    }
+
+    /// No more nodes than the tree has left unvisited.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.tree.len().saturating_sub(self.visited)))
+    }
+}
+
+impl<'a> EntityTreeIterator<'a> {
+    fn advance(&mut self) -> Option<Entity> {
         if let Some(current) = self.current {
             if !self.tree.children.contains_key(&current) {
                 panic!("EntityTreeIterator: {current} not found in tree");
@@ -125,7 +587,10 @@ impl<'a> Iterator for EntityTreeIterator<'a> {
                 return self.current;
             } else {
                 let mut iter_node = current;
-                while let Some(parent) = self.tree.parent[&iter_node] {
+                while Some(iter_node) != self.start {
+                    let Some(parent) = self.tree.parent[&iter_node] else {
+                        break;
+                    };
                     let siblings = &self.tree.children[&parent];
 
                     let sibling_index = siblings.iter().position(|s| *s == iter_node).unwrap() + 1;
@@ -136,10 +601,12 @@ impl<'a> Iterator for EntityTreeIterator<'a> {
                         iter_node = parent;
                     }
                 }
-                return None;
+                self.start = self.pending.pop();
+                self.current = self.start;
+                return self.current;
             }
         }
-        self.current = self.tree.root;
+        self.current = self.start;
         self.current
     }
 }
@@ -189,4 +656,672 @@ mod tests {
         assert_eq!(iter.next(), Some(child3));
         assert_eq!(iter.next(), None);
     }
+
+    #[test]
+    fn test_connected_components() {
+        let mut tree = super::EntityTree::default();
+
+        let parent1 = super::Entity::from(1);
+        let child1 = super::Entity::from(2);
+        let parent2 = super::Entity::from(3);
+        let child2 = super::Entity::from(4);
+
+        tree.insert_node(parent1);
+        tree.insert_node(parent2);
+        tree.add_child(parent1, child1).unwrap();
+        tree.add_child(parent2, child2).unwrap();
+
+        assert_eq!(
+            tree.connected_components(),
+            vec![vec![parent1, child1], vec![parent2, child2]]
+        );
+    }
+
+    #[test]
+    fn test_add_child_to_root() {
+        let root = super::Entity::from(1);
+        let child = super::Entity::from(2);
+
+        let mut tree = super::EntityTree::default();
+        tree.set_root(root);
+        tree.add_child(root, child).unwrap();
+        assert_eq!(tree.get_children(root).unwrap(), &vec![child]);
+        assert_eq!(tree.get_parent(child).unwrap(), &root);
+
+        let mut tree = super::EntityTree::new(root).unwrap();
+        tree.add_child(root, child).unwrap();
+        assert_eq!(tree.get_children(root).unwrap(), &vec![child]);
+    }
+
+    #[test]
+    fn test_add_child_errors() {
+        let mut tree = super::EntityTree::default();
+        let missing = super::Entity::from(5);
+        let child = super::Entity::from(2);
+
+        assert!(matches!(
+            tree.add_child(super::Entity::none(), child),
+            Err(super::EcsError::NoRootEntity)
+        ));
+        assert!(matches!(
+            tree.add_child(missing, child),
+            Err(super::EcsError::EntityNotFound(entity, _)) if entity == missing
+        ));
+
+        let root = super::Entity::from(1);
+        tree.set_root(root);
+        tree.add_child(missing, child).unwrap();
+        assert_eq!(tree.get_parent(child).unwrap(), &root);
+    }
+
+    #[test]
+    fn test_siblings() {
+        let root = super::Entity::from(1);
+        let child1 = super::Entity::from(2);
+        let child2 = super::Entity::from(3);
+        let child3 = super::Entity::from(4);
+
+        let mut tree = super::EntityTree::new(root).unwrap();
+        tree.add_child(root, child1).unwrap();
+        tree.add_child(root, child2).unwrap();
+        tree.add_child(root, child3).unwrap();
+
+        assert_eq!(tree.next_sibling(child1), Some(child2));
+        assert_eq!(tree.next_sibling(child2), Some(child3));
+        assert_eq!(tree.next_sibling(child3), None);
+
+        assert_eq!(tree.prev_sibling(child3), Some(child2));
+        assert_eq!(tree.prev_sibling(child2), Some(child1));
+        assert_eq!(tree.prev_sibling(child1), None);
+
+        assert_eq!(tree.next_sibling(root), None);
+        assert_eq!(tree.prev_sibling(root), None);
+    }
+
+    #[test]
+    fn test_remove() {
+        let root = super::Entity::from(1);
+        let child = super::Entity::from(2);
+        let grandchild = super::Entity::from(3);
+
+        let mut tree = super::EntityTree::new(root).unwrap();
+        tree.add_child(root, child).unwrap();
+        tree.add_child(child, grandchild).unwrap();
+
+        tree.remove(child);
+        assert!(!tree.contains(child));
+        assert!(tree.get_children(root).unwrap().is_empty());
+        assert!(tree.get_parent(grandchild).is_err());
+        assert_eq!(tree.into_iter().collect::<Vec<_>>(), vec![root]);
+    }
+
+    #[test]
+    fn test_compact() {
+        let root = super::Entity::from(1);
+        let parent1 = super::Entity::from(2);
+        let parent2 = super::Entity::from(3);
+
+        let mut tree = super::EntityTree::new(root).unwrap();
+        tree.add_child(root, parent1).unwrap();
+        tree.add_child(root, parent2).unwrap();
+        for id in 4..10 {
+            let parent = if id % 2 == 0 { parent1 } else { parent2 };
+            tree.add_child(parent, super::Entity::from(id)).unwrap();
+        }
+
+        for id in [4, 5, 8] {
+            tree.mark_for_deletion(super::Entity::from(id));
+        }
+        assert!(tree.is_marked(super::Entity::from(4)));
+
+        let removed = tree.compact();
+        assert_eq!(removed.len(), 3);
+        assert!(tree.marked.is_empty());
+        assert_eq!(
+            tree.get_children(parent1).unwrap(),
+            &vec![super::Entity::from(6)]
+        );
+        assert_eq!(
+            tree.get_children(parent2).unwrap(),
+            &vec![super::Entity::from(7), super::Entity::from(9)]
+        );
+        for id in [4, 5, 8] {
+            assert!(!tree.contains(super::Entity::from(id)));
+        }
+        assert_eq!(tree.into_iter().count(), 6);
+    }
+
+    #[test]
+    fn test_reparent_at() {
+        let root = super::Entity::from(1);
+        let parent1 = super::Entity::from(2);
+        let parent2 = super::Entity::from(3);
+        let moved = super::Entity::from(4);
+        let moved_child = super::Entity::from(5);
+        let existing1 = super::Entity::from(6);
+        let existing2 = super::Entity::from(7);
+
+        let mut tree = super::EntityTree::new(root).unwrap();
+        tree.add_child(root, parent1).unwrap();
+        tree.add_child(root, parent2).unwrap();
+        tree.add_child(parent1, moved).unwrap();
+        tree.add_child(moved, moved_child).unwrap();
+        tree.add_child(parent2, existing1).unwrap();
+        tree.add_child(parent2, existing2).unwrap();
+
+        tree.reparent_at(moved, parent2, 1).unwrap();
+        assert_eq!(
+            tree.get_children(parent2).unwrap(),
+            &vec![existing1, moved, existing2]
+        );
+        assert!(tree.get_children(parent1).unwrap().is_empty());
+        assert_eq!(tree.get_parent(moved).unwrap(), &parent2);
+        assert_eq!(tree.get_parent(moved_child).unwrap(), &moved);
+
+        tree.reparent_at(existing1, parent2, 10).unwrap();
+        assert_eq!(
+            tree.get_children(parent2).unwrap(),
+            &vec![moved, existing2, existing1]
+        );
+
+        assert!(matches!(
+            tree.reparent_at(parent2, moved_child, 0),
+            Err(super::EcsError::HierarchyCycle(entity)) if entity == parent2
+        ));
+        assert!(tree.reparent(moved, super::Entity::from(42)).is_err());
+    }
+
+    #[test]
+    fn test_iter_depth_limited() {
+        let mut tree = super::EntityTree::default();
+
+        let root = super::Entity::from(1);
+        let child1 = super::Entity::from(2);
+        let child2 = super::Entity::from(3);
+        let grandchild = super::Entity::from(4);
+
+        tree.insert_node(root);
+        tree.set_root(root);
+
+        tree.add_child(root, child1).unwrap();
+        tree.add_child(root, child2).unwrap();
+        tree.add_child(child2, grandchild).unwrap();
+
+        let visited: Vec<_> = tree.iter_depth_limited(1).collect();
+        assert_eq!(visited, vec![(root, 0), (child1, 1), (child2, 1)]);
+
+        let visited: Vec<_> = tree.iter_depth_limited(2).collect();
+        assert_eq!(visited.last(), Some(&(grandchild, 2)));
+        assert_eq!(
+            tree.iter_depth_limited(0).collect::<Vec<_>>(),
+            vec![(root, 0)]
+        );
+    }
+
+    #[test]
+    fn test_ancestors() {
+        let root = super::Entity::from(1);
+        let child = super::Entity::from(2);
+        let grandchild = super::Entity::from(3);
+
+        let mut tree = super::EntityTree::new(root).unwrap();
+        tree.add_child(root, child).unwrap();
+        tree.add_child(child, grandchild).unwrap();
+
+        assert_eq!(
+            tree.ancestors(grandchild).collect::<Vec<_>>(),
+            vec![child, root]
+        );
+        assert_eq!(tree.ancestors(root).count(), 0);
+    }
+
+    #[test]
+    fn test_iter_from() {
+        let mut tree = super::EntityTree::default();
+        let root = super::Entity::from(1);
+        let branch = super::Entity::from(2);
+        let leaf1 = super::Entity::from(3);
+        let leaf2 = super::Entity::from(4);
+        let sibling = super::Entity::from(5);
+
+        tree.insert_node(root);
+        tree.set_root(root);
+        tree.add_child(root, branch).unwrap();
+        tree.add_child(branch, leaf1).unwrap();
+        tree.add_child(branch, leaf2).unwrap();
+        tree.add_child(root, sibling).unwrap();
+
+        assert_eq!(
+            tree.iter_from(branch).collect::<Vec<_>>(),
+            vec![branch, leaf1, leaf2]
+        );
+        assert_eq!(tree.iter_from(leaf2).collect::<Vec<_>>(), vec![leaf2]);
+        assert_eq!(tree.iter_from(super::Entity::from(9)).count(), 0);
+    }
+
+    #[test]
+    fn test_add_child_rejects_self_parent_and_cycles() {
+        let mut tree = super::EntityTree::default();
+        let root = super::Entity::from(1);
+        let child = super::Entity::from(2);
+        let grandchild = super::Entity::from(3);
+
+        tree.insert_node(root);
+        tree.set_root(root);
+        tree.add_child(root, child).unwrap();
+        tree.add_child(child, grandchild).unwrap();
+        let before = tree.clone();
+
+        assert!(matches!(
+            tree.add_child(child, child),
+            Err(super::EcsError::SelfParent(e)) if e == child
+        ));
+        assert!(matches!(
+            tree.add_child(grandchild, root),
+            Err(super::EcsError::HierarchyCycle(e)) if e == root
+        ));
+        assert_eq!(tree.children, before.children);
+        assert_eq!(tree.parent, before.parent);
+        assert_eq!(tree.iter_from(root).count(), 3);
+    }
+
+    #[test]
+    fn test_par_for_each_subtree() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let mut tree = super::EntityTree::default();
+        let root = super::Entity::from(0);
+        tree.insert_node(root);
+        tree.set_root(root);
+        for group in 1..=4 {
+            let group_root = super::Entity::from(group);
+            tree.add_child(root, group_root).unwrap();
+            for leaf in 0..5 {
+                tree.add_child(group_root, super::Entity::from(10 + group * 5 + leaf))
+                    .unwrap();
+            }
+        }
+
+        let visits: Vec<AtomicUsize> = (0..40).map(|_| AtomicUsize::new(0)).collect();
+        tree.par_for_each_subtree(|entity| {
+            visits[entity.0 as usize].fetch_add(1, Ordering::Relaxed);
+        });
+
+        for entity in &tree {
+            assert_eq!(visits[entity.0 as usize].load(Ordering::Relaxed), 1);
+        }
+        let total: usize = visits.iter().map(|v| v.load(Ordering::Relaxed)).sum();
+        assert_eq!(total, tree.len());
+    }
+
+    #[test]
+    fn test_child_count() {
+        let mut tree = super::EntityTree::default();
+        let root = super::Entity::from(1);
+        let child1 = super::Entity::from(2);
+        let child2 = super::Entity::from(3);
+
+        tree.insert_node(root);
+        tree.set_root(root);
+        tree.add_child(root, child1).unwrap();
+        tree.add_child(root, child2).unwrap();
+
+        assert_eq!(tree.child_count(root), 2);
+        assert!(tree.has_children(root));
+        assert_eq!(tree.child_count(child1), 0);
+        assert!(!tree.has_children(child1));
+        assert_eq!(tree.child_count(super::Entity::from(9)), 0);
+        assert!(!tree.has_children(super::Entity::from(9)));
+    }
+
+    #[test]
+    fn test_iter_post_order() {
+        let mut tree = super::EntityTree::default();
+
+        let root = super::Entity::from(1);
+        let child1 = super::Entity::from(2);
+        let child2 = super::Entity::from(3);
+        let child3 = super::Entity::from(4);
+
+        tree.insert_node(root);
+        tree.set_root(root);
+
+        tree.add_child(root, child1).unwrap();
+        tree.add_child(root, child2).unwrap();
+        tree.add_child(child2, child3).unwrap();
+
+        assert_eq!(
+            tree.iter_post_order().collect::<Vec<_>>(),
+            vec![child1, child3, child2, root]
+        );
+        assert_eq!(super::EntityTree::default().iter_post_order().count(), 0);
+    }
+
+    #[test]
+    fn test_nodes_at_depth() {
+        let mut tree = super::EntityTree::default();
+        let root = super::Entity::from(1);
+        let a = super::Entity::from(2);
+        let b = super::Entity::from(3);
+        let a1 = super::Entity::from(4);
+        let b1 = super::Entity::from(5);
+        let b2 = super::Entity::from(6);
+
+        tree.insert_node(root);
+        tree.set_root(root);
+        tree.add_child(root, a).unwrap();
+        tree.add_child(root, b).unwrap();
+        tree.add_child(a, a1).unwrap();
+        tree.add_child(b, b1).unwrap();
+        tree.add_child(b, b2).unwrap();
+
+        assert_eq!(tree.nodes_at_depth(0), vec![root]);
+        assert_eq!(tree.nodes_at_depth(1), vec![a, b]);
+        assert_eq!(tree.nodes_at_depth(2), vec![a1, b1, b2]);
+        assert!(tree.nodes_at_depth(3).is_empty());
+    }
+
+    #[test]
+    fn test_forest_iteration() {
+        let mut tree = super::EntityTree::default();
+        let first = super::Entity::from(1);
+        let first_child = super::Entity::from(2);
+        let second = super::Entity::from(3);
+        let second_child = super::Entity::from(4);
+        let second_grandchild = super::Entity::from(5);
+
+        tree.add_root(first);
+        tree.add_root(second);
+        tree.add_child(first, first_child).unwrap();
+        tree.add_child(second, second_child).unwrap();
+        tree.add_child(second_child, second_grandchild).unwrap();
+
+        assert_eq!(tree.root, Some(first));
+        assert_eq!(tree.roots(), &[first, second]);
+        assert_eq!(
+            tree.into_iter().collect::<Vec<_>>(),
+            vec![first, first_child, second, second_child, second_grandchild]
+        );
+
+        tree.remove(first);
+        assert_eq!(tree.root, Some(second));
+        assert_eq!(tree.roots(), &[second]);
+        assert_eq!(tree.into_iter().count(), 3);
+    }
+
+    #[test]
+    fn test_detach_subtree() {
+        let root = super::Entity::from(1);
+        let a = super::Entity::from(2);
+        let b = super::Entity::from(3);
+        let c = super::Entity::from(4);
+        let mut tree = super::EntityTree::new(root).unwrap();
+        tree.add_child(root, a).unwrap();
+        tree.add_child(a, b).unwrap();
+        tree.add_child(root, c).unwrap();
+
+        let detached = tree.detach_subtree(a).unwrap();
+        assert_eq!(detached.root, Some(a));
+        assert_eq!(detached.roots(), &[a]);
+        assert_eq!(detached.get_children(a).unwrap(), &vec![b]);
+        assert_eq!(detached.get_parent(b).unwrap(), &a);
+        assert!(detached.get_parent(a).is_err());
+        assert_eq!(detached.into_iter().collect::<Vec<_>>(), vec![a, b]);
+
+        assert_eq!(tree.len(), 2);
+        assert!(!tree.contains(a) && !tree.contains(b));
+        assert_eq!(tree.get_children(root).unwrap(), &vec![c]);
+        assert_eq!(tree.into_iter().collect::<Vec<_>>(), vec![root, c]);
+        assert!(tree.detach_subtree(a).is_err());
+    }
+
+    #[test]
+    fn test_flatten() {
+        let mut tree = super::EntityTree::default();
+
+        let root = super::Entity::from(1);
+        let child1 = super::Entity::from(2);
+        let child2 = super::Entity::from(3);
+        let child3 = super::Entity::from(4);
+
+        tree.insert_node(root);
+        tree.set_root(root);
+
+        tree.add_child(root, child1).unwrap();
+        tree.add_child(root, child2).unwrap();
+        tree.add_child(child2, child3).unwrap();
+
+        assert_eq!(
+            tree.flatten(),
+            vec![
+                (root, None, 0),
+                (child1, Some(root), 1),
+                (child2, Some(root), 1),
+                (child3, Some(child2), 2),
+            ]
+        );
+        assert!(super::EntityTree::default().flatten().is_empty());
+    }
+
+    #[test]
+    fn test_new() {
+        let root = super::Entity::from(1);
+        let tree = super::EntityTree::new(root).unwrap();
+        assert_eq!(tree.root, Some(root));
+        assert_eq!(tree.roots(), &[root]);
+        assert_eq!(tree.len(), 1);
+
+        assert!(matches!(
+            super::EntityTree::new(super::Entity::none()),
+            Err(super::EcsError::NoRootEntity)
+        ));
+    }
+
+    #[test]
+    fn test_move_subtree() {
+        let root = super::Entity::from(1);
+        let target = super::Entity::from(2);
+        let moved = super::Entity::from(3);
+        let child = super::Entity::from(4);
+        let grandchild1 = super::Entity::from(5);
+        let grandchild2 = super::Entity::from(6);
+
+        let mut tree = super::EntityTree::new(root).unwrap();
+        tree.add_child(root, target).unwrap();
+        tree.add_child(root, moved).unwrap();
+        tree.add_child(moved, child).unwrap();
+        tree.add_child(child, grandchild1).unwrap();
+        tree.add_child(child, grandchild2).unwrap();
+
+        tree.move_subtree(moved, target).unwrap();
+        assert_eq!(tree.get_children(root).unwrap(), &vec![target]);
+        assert_eq!(tree.get_children(target).unwrap(), &vec![moved]);
+        assert_eq!(tree.get_parent(child).unwrap(), &moved);
+        assert_eq!(
+            tree.get_children(child).unwrap(),
+            &vec![grandchild1, grandchild2]
+        );
+        assert_eq!(
+            tree.ancestors(grandchild2).collect::<Vec<_>>(),
+            vec![child, moved, target, root]
+        );
+
+        assert!(matches!(
+            tree.move_subtree(moved, grandchild1),
+            Err(super::EcsError::HierarchyCycle(entity)) if entity == moved
+        ));
+        assert_eq!(tree.get_parent(moved).unwrap(), &target);
+    }
+
+    #[test]
+    fn test_size_hint() {
+        let root = super::Entity::from(1);
+        let child1 = super::Entity::from(2);
+        let child2 = super::Entity::from(3);
+
+        let mut tree = super::EntityTree::new(root).unwrap();
+        tree.add_child(root, child1).unwrap();
+        tree.add_child(child1, child2).unwrap();
+
+        let mut iter = tree.into_iter();
+        assert_eq!(iter.size_hint(), (0, Some(tree.len())));
+        iter.next();
+        assert_eq!(iter.size_hint(), (0, Some(2)));
+        assert_eq!(iter.by_ref().count(), 2);
+        assert_eq!(iter.size_hint(), (0, Some(0)));
+        assert_eq!(tree.iter_from(child1).size_hint(), (0, Some(3)));
+    }
+
+    #[test]
+    fn test_common_ancestor() {
+        let root = super::Entity::from(1);
+        let left = super::Entity::from(2);
+        let right = super::Entity::from(3);
+        let left_leaf1 = super::Entity::from(4);
+        let left_leaf2 = super::Entity::from(5);
+        let right_leaf = super::Entity::from(6);
+        let detached = super::Entity::from(7);
+
+        let mut tree = super::EntityTree::new(root).unwrap();
+        tree.add_child(root, left).unwrap();
+        tree.add_child(root, right).unwrap();
+        tree.add_child(left, left_leaf1).unwrap();
+        tree.add_child(left, left_leaf2).unwrap();
+        tree.add_child(right, right_leaf).unwrap();
+        tree.add_root(detached);
+
+        assert_eq!(tree.common_ancestor(left_leaf1, right_leaf), Some(root));
+        assert_eq!(tree.common_ancestor(left_leaf1, left_leaf2), Some(left));
+        assert_eq!(tree.common_ancestor(left, left_leaf2), Some(left));
+        assert_eq!(tree.common_ancestor(left_leaf1, detached), None);
+        assert_eq!(
+            tree.common_ancestor(left_leaf1, super::Entity::from(42)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_set_root_keeps_other_roots() {
+        let first = super::Entity::from(1);
+        let second = super::Entity::from(2);
+        let third = super::Entity::from(3);
+
+        let mut tree = super::EntityTree::new(first).unwrap();
+        tree.add_root(third);
+        tree.add_root(second);
+        assert_eq!(tree.roots(), &[first, third, second]);
+
+        tree.set_root(second);
+        assert_eq!(tree.root, Some(second));
+        assert_eq!(tree.roots(), &[second, first, third]);
+        assert_eq!(
+            tree.into_iter().collect::<Vec<_>>(),
+            vec![second, first, third]
+        );
+    }
+
+    #[test]
+    fn test_compact_marked_root() {
+        let first = super::Entity::from(1);
+        let child = super::Entity::from(2);
+        let second = super::Entity::from(3);
+
+        let mut tree = super::EntityTree::new(first).unwrap();
+        tree.add_child(first, child).unwrap();
+        tree.add_root(second);
+        tree.mark_for_deletion(first);
+        tree.compact();
+
+        assert_eq!(tree.root, Some(second));
+        assert_eq!(tree.roots(), &[second]);
+        assert_eq!(tree.into_iter().collect::<Vec<_>>(), vec![second]);
+    }
+
+    #[test]
+    fn test_add_child_rejects_attached_child() {
+        let root = super::Entity::from(1);
+        let other = super::Entity::from(2);
+        let child = super::Entity::from(3);
+        let grandchild = super::Entity::from(4);
+
+        let mut tree = super::EntityTree::new(root).unwrap();
+        tree.add_child(root, other).unwrap();
+        tree.add_child(root, child).unwrap();
+        tree.add_child(child, grandchild).unwrap();
+
+        assert!(matches!(
+            tree.add_child(root, child),
+            Err(super::EcsError::EntityAlreadyExists(entity)) if entity == child
+        ));
+        assert!(tree.add_child(other, child).is_err());
+        assert_eq!(tree.get_children(root).unwrap(), &vec![other, child]);
+        assert!(tree.get_children(other).unwrap().is_empty());
+        assert_eq!(tree.get_children(child).unwrap(), &vec![grandchild]);
+        assert_eq!(tree.into_iter().count(), 4);
+    }
+
+    #[test]
+    fn test_add_child_keeps_subtree_of_detached_node() {
+        let root = super::Entity::from(1);
+        let detached = super::Entity::from(2);
+        let grandchild = super::Entity::from(3);
+
+        let mut tree = super::EntityTree::new(root).unwrap();
+        tree.add_root(detached);
+        tree.add_child(detached, grandchild).unwrap();
+        tree.add_child(root, detached).unwrap();
+
+        assert_eq!(tree.get_children(detached).unwrap(), &vec![grandchild]);
+        assert_eq!(tree.roots(), &[root]);
+        assert_eq!(
+            tree.into_iter().collect::<Vec<_>>(),
+            vec![root, detached, grandchild]
+        );
+    }
+
+    #[test]
+    fn test_deserialize_without_marked() {
+        let root = super::Entity::from(1);
+        let child = super::Entity::from(2);
+        let mut tree = super::EntityTree::new(root).unwrap();
+        tree.add_child(root, child).unwrap();
+
+        // Trees saved before marking existed have neither `marked` nor `roots`.
+        let mut value = serde_json::to_value(&tree).unwrap();
+        let fields = value.as_object_mut().unwrap();
+        fields.remove("marked");
+        fields.remove("roots");
+        let loaded: super::EntityTree = serde_json::from_value(value).unwrap();
+
+        assert!(loaded.marked.is_empty());
+        assert_eq!(loaded.get_children(root).unwrap(), &vec![child]);
+    }
+
+    #[test]
+    fn test_promote_attached_child() {
+        let root = super::Entity::from(1);
+        let child = super::Entity::from(2);
+        let grandchild = super::Entity::from(3);
+
+        let mut tree = super::EntityTree::new(root).unwrap();
+        tree.add_child(root, child).unwrap();
+        tree.add_child(child, grandchild).unwrap();
+        tree.add_root(child);
+        assert!(tree.get_parent(child).is_err());
+        assert!(tree.get_children(root).unwrap().is_empty());
+        assert_eq!(
+            tree.into_iter().collect::<Vec<_>>(),
+            vec![root, child, grandchild]
+        );
+
+        let mut tree = super::EntityTree::new(root).unwrap();
+        tree.add_child(root, child).unwrap();
+        tree.add_child(child, grandchild).unwrap();
+        tree.set_root(grandchild);
+        assert_eq!(tree.roots(), &[grandchild, root]);
+        assert_eq!(
+            tree.into_iter().collect::<Vec<_>>(),
+            vec![grandchild, root, child]
+        );
+    }
 }
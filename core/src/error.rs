@@ -6,8 +6,26 @@ pub enum FindEntityLocation {
     ComponentManager,
 }
 
-#[derive(Debug, Copy, Clone)]
+/// Errors returned by the tree and the manager.
+///
+/// Since serialization was added this is `Clone` but no longer `Copy`, because
+/// [`EcsError::Serialization`] and [`EcsError::ValidationFailed`] carry owned messages.
+/// Code that copied an error out of a reference needs an explicit `.clone()`.
+#[derive(Debug, Clone)]
 pub enum EcsError {
     EntityNotFound(Entity, FindEntityLocation),
     NoRootEntity,
+    /// The entity is alive but has no component of the named type.
+    ComponentNotFound(Entity, &'static str),
+    /// The entity has a component stored for the named type, but it holds another type.
+    ComponentTypeMismatch(Entity, &'static str),
+    EntityAlreadyExists(Entity),
+    /// The entity was given itself as parent.
+    SelfParent(Entity),
+    /// The entity would become its own ancestor.
+    HierarchyCycle(Entity),
+    /// Encoding or decoding failed, the message comes from serde or names the bad record.
+    Serialization(String),
+    /// A registered validator rejected the component.
+    ValidationFailed(String),
 }
@@ -1,5 +1,17 @@
+mod bloom;
+pub mod builder;
+pub mod bundle;
+mod column;
+pub mod command_buffer;
 pub mod component;
+pub mod dense;
+pub mod diff;
 pub mod entity;
 pub mod entity_component_manager;
 pub mod entity_tree;
 pub mod error;
+pub mod query;
+pub mod refcell_manager;
+pub mod serialization;
+pub mod storage;
+pub mod view;
@@ -0,0 +1,132 @@
+use std::marker::PhantomData;
+
+use crate::{entity::Entity, entity_component_manager::EntityComponentManager};
+
+/// A set of component types that can be fetched together for one entity.
+///
+/// Implemented for tuples of shared references up to arity 4, so
+/// `manager.query::<(&Position, &Velocity)>()` yields `(Entity, &Position, &Velocity)`.
+pub trait Query {
+    type Item<'a>;
+
+    /// Pushes every entity that could match the query into `out`.
+    fn candidates(manager: &EntityComponentManager, out: &mut Vec<Entity>);
+
+    /// Fetches the query's components for `entity`, or `None` if one is missing.
+    fn fetch(manager: &EntityComponentManager, entity: Entity) -> Option<Self::Item<'_>>;
+}
+
+/// A prepared query that keeps its candidate buffer between runs.
+///
+/// Created by [`EntityComponentManager::build_query`].
+pub struct QueryState<Q> {
+    candidates: Vec<Entity>,
+    _query: PhantomData<fn() -> Q>,
+}
+
+impl<Q: Query> QueryState<Q> {
+    pub(crate) fn new() -> Self {
+        Self {
+            candidates: Vec::new(),
+            _query: PhantomData,
+        }
+    }
+
+    /// Runs the query against the current state of `manager`.
+    pub fn iter<'a>(
+        &'a mut self,
+        manager: &'a EntityComponentManager,
+    ) -> impl Iterator<Item = Q::Item<'a>> + 'a {
+        self.candidates.clear();
+        Q::candidates(manager, &mut self.candidates);
+        self.candidates
+            .iter()
+            .filter_map(move |entity| Q::fetch(manager, *entity))
+    }
+}
+
+macro_rules! impl_query {
+    ($first:ident $(, $rest:ident)*) => {
+        impl<'q, $first: 'static, $($rest: 'static),*> Query for (&'q $first, $(&'q $rest,)*) {
+            type Item<'a> = (Entity, &'a $first, $(&'a $rest,)*);
+
+            fn candidates(manager: &EntityComponentManager, out: &mut Vec<Entity>) {
+                out.extend(manager.iter_component::<$first>().map(|(entity, _)| entity));
+            }
+
+            fn fetch(manager: &EntityComponentManager, entity: Entity) -> Option<Self::Item<'_>> {
+                Some((
+                    entity,
+                    manager.get_component::<$first>(entity)?,
+                    $(manager.get_component::<$rest>(entity)?,)*
+                ))
+            }
+        }
+    };
+}
+
+impl_query!(A);
+impl_query!(A, B);
+impl_query!(A, B, C);
+impl_query!(A, B, C, D);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Position(i32);
+    #[derive(Debug, PartialEq)]
+    struct Velocity(i32);
+    #[derive(Debug, PartialEq)]
+    struct Mass(i32);
+
+    #[test]
+    fn query_two_components() {
+        let mut manager = EntityComponentManager::default();
+        let both = manager.create_entity();
+        let position_only = manager.create_entity();
+        let velocity_only = manager.create_entity();
+        manager.insert_component(both, Position(1));
+        manager.insert_component(both, Velocity(2));
+        manager.insert_component(position_only, Position(3));
+        manager.insert_component(velocity_only, Velocity(4));
+
+        let result = manager.query::<(&Position, &Velocity)>();
+        assert_eq!(result, vec![(both, &Position(1), &Velocity(2))]);
+    }
+
+    #[test]
+    fn query_three_components() {
+        let mut manager = EntityComponentManager::default();
+        let all = manager.create_entity();
+        let missing_mass = manager.create_entity();
+        manager.insert_component(all, Position(1));
+        manager.insert_component(all, Velocity(2));
+        manager.insert_component(all, Mass(3));
+        manager.insert_component(missing_mass, Position(4));
+        manager.insert_component(missing_mass, Velocity(5));
+
+        let result = manager.query::<(&Position, &Velocity, &Mass)>();
+        assert_eq!(result, vec![(all, &Position(1), &Velocity(2), &Mass(3))]);
+    }
+
+    #[test]
+    fn query_state_reused_across_frames() {
+        let mut manager = EntityComponentManager::default();
+        let first = manager.create_entity();
+        let second = manager.create_entity();
+        manager.insert_component(first, Position(1));
+        manager.insert_component(first, Velocity(1));
+        manager.insert_component(second, Position(2));
+
+        let mut state = manager.build_query::<(&Position, &Velocity)>();
+        let frame1: Vec<_> = state.iter(&manager).map(|(entity, _, _)| entity).collect();
+        assert_eq!(frame1, vec![first]);
+
+        manager.insert_component(second, Velocity(2));
+        manager.remove_component::<Velocity>(first);
+        let frame2: Vec<_> = state.iter(&manager).map(|(entity, _, _)| entity).collect();
+        assert_eq!(frame2, vec![second]);
+    }
+}
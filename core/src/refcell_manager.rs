@@ -0,0 +1,104 @@
+use std::{
+    any::Any,
+    cell::{Ref, RefCell, RefMut},
+    collections::{HashMap, HashSet},
+};
+
+use crate::entity::Entity;
+
+type RefCellStore = HashMap<(Entity, &'static str), RefCell<Box<dyn Any>>>;
+
+/// A single-threaded manager whose components are borrowed through `&self`.
+///
+/// Every component sits in its own `RefCell`, so [`Self::get_component_mut`] works
+/// while other components are borrowed. Conflicting borrows of the same component
+/// panic at runtime instead of failing to compile.
+#[derive(Default)]
+pub struct RefCellEntityComponentManager {
+    component_store: RefCellStore,
+    entities: HashSet<Entity>,
+    entity_counter: u64,
+}
+
+impl RefCellEntityComponentManager {
+    pub fn create_entity(&mut self) -> Entity {
+        self.entity_counter += 1;
+        let entity = Entity(self.entity_counter);
+        self.entities.insert(entity);
+        entity
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        self.entities.contains(&entity)
+    }
+
+    pub fn delete_entity(&mut self, entity: Entity) {
+        self.entities.remove(&entity);
+        self.component_store.retain(|(e, _), _| *e != entity);
+    }
+
+    pub fn insert_component<T: 'static>(&mut self, entity: Entity, component: T) {
+        self.component_store.insert(
+            (entity, std::any::type_name::<T>()),
+            RefCell::new(Box::new(component)),
+        );
+    }
+
+    /// Panics if the component is currently borrowed mutably.
+    pub fn get_component<T: 'static>(&self, entity: Entity) -> Option<Ref<'_, T>> {
+        let cell = self
+            .component_store
+            .get(&(entity, std::any::type_name::<T>()))?;
+        Ref::filter_map(cell.borrow(), |component| component.downcast_ref::<T>()).ok()
+    }
+
+    /// Panics if the component is currently borrowed.
+    pub fn get_component_mut<T: 'static>(&self, entity: Entity) -> Option<RefMut<'_, T>> {
+        let cell = self
+            .component_store
+            .get(&(entity, std::any::type_name::<T>()))?;
+        RefMut::filter_map(cell.borrow_mut(), |component| component.downcast_mut::<T>()).ok()
+    }
+
+    pub fn remove_component<T: 'static>(&mut self, entity: Entity) -> Option<T> {
+        self.component_store
+            .remove(&(entity, std::any::type_name::<T>()))?
+            .into_inner()
+            .downcast::<T>()
+            .ok()
+            .map(|component| *component)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mutate_through_shared_reference() {
+        let mut manager = RefCellEntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 1);
+        manager.insert_component(entity, 2.0f32);
+
+        let shared = &manager;
+        let float = shared.get_component::<f32>(entity).unwrap();
+        *shared.get_component_mut::<i32>(entity).unwrap() += *float as i32;
+        drop(float);
+
+        assert_eq!(*manager.get_component::<i32>(entity).unwrap(), 3);
+        assert_eq!(manager.remove_component::<i32>(entity), Some(3));
+        assert!(manager.get_component::<i32>(entity).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn conflicting_borrow_panics() {
+        let mut manager = RefCellEntityComponentManager::default();
+        let entity = manager.create_entity();
+        manager.insert_component(entity, 1);
+
+        let _reading = manager.get_component::<i32>(entity).unwrap();
+        let _writing = manager.get_component_mut::<i32>(entity);
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    any::{Any, TypeId},
+    collections::{HashMap, HashSet},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    component::SerializableComponent, entity::Entity, entity_tree::EntityTree, error::EcsError,
+};
+
+/// Upgrades the serialized data of a component by one version.
+pub type Migration = fn(Value) -> Value;
+
+/// Type-erased (de)serialization functions of a registered component type.
+#[derive(Clone)]
+pub(crate) struct ComponentSerializer {
+    pub serialize: fn(&dyn Any) -> Result<Value, EcsError>,
+    pub deserialize: fn(Value) -> Result<Box<dyn Any>, EcsError>,
+    /// Schema version written into every record of this type.
+    pub version: u32,
+    /// Migrations keyed by the version they upgrade from.
+    pub migrations: HashMap<u32, Migration>,
+}
+
+impl ComponentSerializer {
+    pub fn of<T: SerializableComponent>(version: u32) -> Self {
+        Self {
+            serialize: serialize_component::<T>,
+            deserialize: deserialize_component::<T>,
+            version,
+            migrations: HashMap::new(),
+        }
+    }
+
+    /// Brings the data of `record` up to the registered version.
+    pub fn migrate(&self, record: ComponentRecord) -> Result<Value, EcsError> {
+        if record.version > self.version {
+            return Err(EcsError::Serialization(format!(
+                "{} record has version {} but only version {} is known",
+                record.type_name, record.version, self.version
+            )));
+        }
+
+        let mut data = record.data;
+        for version in record.version..self.version {
+            let migration = self.migrations.get(&version).ok_or_else(|| {
+                EcsError::Serialization(format!(
+                    "no migration for {} from version {}",
+                    record.type_name, version
+                ))
+            })?;
+            data = migration(data);
+        }
+        Ok(data)
+    }
+}
+
+fn serialize_component<T: SerializableComponent>(component: &dyn Any) -> Result<Value, EcsError> {
+    let component = component.downcast_ref::<T>().ok_or_else(|| {
+        EcsError::Serialization(format!("component is not a {}", std::any::type_name::<T>()))
+    })?;
+    serde_json::to_value(component).map_err(|err| EcsError::Serialization(err.to_string()))
+}
+
+fn deserialize_component<T: SerializableComponent>(data: Value) -> Result<Box<dyn Any>, EcsError> {
+    serde_json::from_value::<T>(data)
+        .map(|component| Box::new(component) as Box<dyn Any>)
+        .map_err(|err| EcsError::Serialization(err.to_string()))
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct ComponentRecord {
+    pub entity: Entity,
+    pub type_name: String,
+    pub version: u32,
+    pub data: Value,
+    /// Position in the insertion order, records are loaded in ascending order.
+    #[serde(default)]
+    pub sequence: u64,
+}
+
+/// The serialized form of one entity and, optionally, its descendants.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct EntityData {
+    /// Records keep the id of the exported entity, it is not reused on spawn.
+    pub components: Vec<ComponentRecord>,
+    #[serde(default)]
+    pub children: Vec<EntityData>,
+}
+
+/// The serialized form of a whole [`crate::entity_component_manager::EntityComponentManager`].
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct WorldData {
+    pub entity_counter: Entity,
+    pub tree: EntityTree,
+    pub names: Vec<(Entity, String)>,
+    pub disabled: Vec<Entity>,
+    pub components: Vec<ComponentRecord>,
+}
+
+/// A captured world state, see [`EntityComponentManager::snapshot`].
+///
+/// [`EntityComponentManager::snapshot`]: crate::entity_component_manager::EntityComponentManager::snapshot
+#[derive(Clone)]
+pub struct WorldSnapshot {
+    pub(crate) data: WorldData,
+    /// Tags are keyed by type and cannot be serialized, so only snapshots carry them.
+    pub(crate) tags: HashMap<TypeId, HashSet<Entity>>,
+}
@@ -0,0 +1,129 @@
+use std::{any::Any, collections::HashMap};
+
+use crate::{dense::DenseStore, entity::Entity};
+
+/// Holds every component of one type, the manager goes through it for all access.
+///
+/// A `HashMap<Entity, T>` is used unless a [`ComponentConfig`] picks another storage.
+pub trait Storage<T>: Any {
+    /// Inserts or replaces the component of `entity`, returning the previous one.
+    fn insert(&mut self, entity: Entity, component: T) -> Option<T>;
+
+    fn get(&self, entity: Entity) -> Option<&T>;
+
+    fn get_mut(&mut self, entity: Entity) -> Option<&mut T>;
+
+    fn remove(&mut self, entity: Entity) -> Option<T>;
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (Entity, &T)> + '_>;
+
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (Entity, &mut T)> + '_>;
+
+    fn contains(&self, entity: Entity) -> bool {
+        self.get(entity).is_some()
+    }
+
+    fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: 'static> Storage<T> for HashMap<Entity, T> {
+    fn insert(&mut self, entity: Entity, component: T) -> Option<T> {
+        HashMap::insert(self, entity, component)
+    }
+
+    fn get(&self, entity: Entity) -> Option<&T> {
+        HashMap::get(self, &entity)
+    }
+
+    fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        HashMap::get_mut(self, &entity)
+    }
+
+    fn remove(&mut self, entity: Entity) -> Option<T> {
+        HashMap::remove(self, &entity)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (Entity, &T)> + '_> {
+        Box::new(HashMap::iter(self).map(|(entity, component)| (*entity, component)))
+    }
+
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (Entity, &mut T)> + '_> {
+        Box::new(HashMap::iter_mut(self).map(|(entity, component)| (*entity, component)))
+    }
+
+    fn contains(&self, entity: Entity) -> bool {
+        self.contains_key(&entity)
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+}
+
+impl<T: 'static> Storage<T> for DenseStore<T> {
+    fn insert(&mut self, entity: Entity, component: T) -> Option<T> {
+        DenseStore::insert(self, entity, component)
+    }
+
+    fn get(&self, entity: Entity) -> Option<&T> {
+        DenseStore::get(self, entity)
+    }
+
+    fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        DenseStore::get_mut(self, entity)
+    }
+
+    fn remove(&mut self, entity: Entity) -> Option<T> {
+        DenseStore::remove(self, entity)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (Entity, &T)> + '_> {
+        Box::new(DenseStore::iter(self))
+    }
+
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (Entity, &mut T)> + '_> {
+        Box::new(DenseStore::iter_mut(self))
+    }
+
+    fn contains(&self, entity: Entity) -> bool {
+        DenseStore::contains(self, entity)
+    }
+
+    fn len(&self) -> usize {
+        DenseStore::len(self)
+    }
+}
+
+/// How the manager stores the components of type `T`.
+///
+/// Registered with
+/// [`EntityComponentManager::configure_component`](crate::entity_component_manager::EntityComponentManager::configure_component).
+pub struct ComponentConfig<T> {
+    pub(crate) storage: Box<dyn Storage<T>>,
+}
+
+impl<T: 'static> Default for ComponentConfig<T> {
+    fn default() -> Self {
+        Self::with_storage(HashMap::new())
+    }
+}
+
+impl<T: 'static> ComponentConfig<T> {
+    /// Stores `T` in a [`DenseStore`].
+    pub fn dense() -> Self {
+        Self::with_storage(DenseStore::default())
+    }
+
+    /// Stores `T` in `storage`, which should start out empty.
+    pub fn with_storage(storage: impl Storage<T>) -> Self {
+        Self {
+            storage: Box::new(storage),
+        }
+    }
+}
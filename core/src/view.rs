@@ -0,0 +1,59 @@
+use std::marker::PhantomData;
+
+use crate::{entity::Entity, entity_component_manager::EntityComponentManager};
+
+/// Access to the `T` components of a manager, created by [`EntityComponentManager::view`].
+pub struct ComponentView<'a, T> {
+    manager: &'a mut EntityComponentManager,
+    _component: PhantomData<fn() -> T>,
+}
+
+impl<'a, T: 'static> ComponentView<'a, T> {
+    pub(crate) fn new(manager: &'a mut EntityComponentManager) -> Self {
+        Self {
+            manager,
+            _component: PhantomData,
+        }
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        self.manager.get_component(entity)
+    }
+
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        self.manager.get_component_mut(entity)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.manager.queue_component::<T>().into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Health(u32);
+
+    #[test]
+    fn view_get_and_iter() {
+        let mut manager = EntityComponentManager::default();
+        let a = manager.create_entity();
+        let b = manager.create_entity();
+        manager.insert_component(a, Health(10));
+        manager.insert_component(b, Health(20));
+        manager.insert_component(b, 1.0f32);
+
+        let mut view = manager.view::<Health>();
+        assert_eq!(view.get(a), Some(&Health(10)));
+        view.get_mut(b).unwrap().0 -= 5;
+        assert_eq!(view.get(b), Some(&Health(15)));
+        let mut all: Vec<_> = view
+            .iter()
+            .map(|(entity, health)| (entity, health.0))
+            .collect();
+        all.sort();
+        assert_eq!(all, vec![(a, 10), (b, 15)]);
+    }
+}